@@ -0,0 +1,151 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::middleware::map_response;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use crate::AppState;
+use crate::api::geojson::{
+    Feature, FeatureCollection, Format, GEOJSON_CONTENT_TYPE, device_feature, track_feature,
+    vary_accept,
+};
+use crate::sensors::{Device, DeviceMeasurements, Trilateration};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TrackQuery {
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    // The route layer only applies to the routes added before it.
+    OpenApiRouter::new()
+        .routes(routes!(index))
+        .routes(routes!(device))
+        .routes(routes!(track))
+        .route_layer(map_response(vary_accept))
+        .routes(routes!(measurements))
+        .route("/{fingerprint}/track.geojson", get(track))
+}
+
+/// The pseudo-devices, also as GeoJSON at `/devices.geojson`.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "devices",
+    responses((status = OK, content(
+        (Vec<Device> = "application/json"),
+        (FeatureCollection = GEOJSON_CONTENT_TYPE),
+    )))
+)]
+pub(super) async fn index(format: Format, State(state): State<AppState>) -> impl IntoResponse {
+    let devices = state.sensor_service.get_devices().await;
+
+    match format {
+        Format::Json => (StatusCode::OK, Json(devices)).into_response(),
+        Format::GeoJson => (
+            StatusCode::OK,
+            devices
+                .iter()
+                .map(device_feature)
+                .collect::<FeatureCollection>(),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{fingerprint}",
+    tag = "devices",
+    params(("fingerprint" = u64, Path, description = "Fingerprint of the device or of one of its addresses")),
+    responses(
+        (status = OK, content(
+            (Device = "application/json"),
+            (Feature = GEOJSON_CONTENT_TYPE),
+        )),
+        (status = NOT_FOUND, description = "Device not found"),
+    )
+)]
+async fn device(
+    format: Format,
+    Path(fingerprint): Path<u64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.sensor_service.get_device(fingerprint).await {
+        Some(device) if format == Format::GeoJson => {
+            (StatusCode::OK, device_feature(&device)).into_response()
+        }
+        Some(device) => (StatusCode::OK, Json(device)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+    }
+}
+
+/// The measurements that are waiting for the next position of the device.
+#[utoipa::path(
+    get,
+    path = "/{fingerprint}/measurements",
+    tag = "devices",
+    params(("fingerprint" = u64, Path, description = "Fingerprint of the device or of one of its addresses")),
+    responses((status = OK, body = DeviceMeasurements), (status = NOT_FOUND, description = "Device not found"))
+)]
+async fn measurements(
+    Path(fingerprint): Path<u64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state
+        .sensor_service
+        .get_device_measurements(fingerprint)
+        .await
+    {
+        Some(measurements) => (StatusCode::OK, Json(measurements)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+    }
+}
+
+/// The positions of the device in the given time range, also as GeoJSON at
+/// `/{fingerprint}/track.geojson`.
+#[utoipa::path(
+    get,
+    path = "/{fingerprint}/track",
+    tag = "devices",
+    params(
+        ("fingerprint" = u64, Path, description = "Fingerprint of the device or of one of its addresses"),
+        TrackQuery
+    ),
+    responses(
+        (status = OK, content(
+            (Vec<Trilateration> = "application/json"),
+            (FeatureCollection = GEOJSON_CONTENT_TYPE),
+        )),
+        (status = NOT_FOUND, description = "Device not found"),
+    )
+)]
+async fn track(
+    format: Format,
+    Path(fingerprint): Path<u64>,
+    Query(query): Query<TrackQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state
+        .sensor_service
+        .get_track(fingerprint, query.since, query.until)
+        .await
+    {
+        Some((id, track)) if format == Format::GeoJson => (
+            StatusCode::OK,
+            FeatureCollection {
+                features: vec![track_feature(id, &track)],
+            },
+        )
+            .into_response(),
+        Some((_, track)) => (StatusCode::OK, Json(track)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+    }
+}
@@ -0,0 +1,322 @@
+use axum::Json;
+use axum::extract::FromRequestParts;
+use axum::http::HeaderValue;
+use axum::http::header::{ACCEPT, CONTENT_TYPE, VARY};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::coords::Georeference;
+use crate::rssi::ErrorEllipse;
+use crate::sensors::{Device, Sensor, Trilateration};
+
+pub const GEOJSON_CONTENT_TYPE: &str = "application/geo+json";
+
+/// Vertices of the accuracy polygons.
+const ELLIPSE_POINTS: usize = 32;
+
+/// Representation of a response, GeoJSON if the client prefers it over JSON or the path ends
+/// with `.geojson`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    GeoJson,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts
+            .headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        match prefers_geojson(&accept) || parts.uri.path().ends_with(".geojson") {
+            true => Ok(Format::GeoJson),
+            false => Ok(Format::Json),
+        }
+    }
+}
+
+/// Appends `Accept` to the `Vary` header of a response whose [`Format`] was negotiated by the
+/// header, so that caches keep the JSON and GeoJSON representations of a URL apart. Applied as a
+/// route layer, the `.geojson` routes do not vary.
+pub async fn vary_accept(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept"));
+
+    response
+}
+
+/// Whether the media ranges of an `Accept` header give GeoJSON a higher quality than JSON, which
+/// stays the default for equal qualities like with `*/*`.
+fn prefers_geojson(accept: &str) -> bool {
+    let ranges: Vec<(&str, f64)> = accept.split(',').filter_map(media_range).collect();
+
+    let geojson = quality(&ranges, GEOJSON_CONTENT_TYPE);
+    geojson > 0.0 && geojson > quality(&ranges, "application/json")
+}
+
+/// Splits a media range into its lowercase type and its quality, 1 unless given.
+fn media_range(range: &str) -> Option<(&str, f64)> {
+    let mut parts = range.split(';').map(str::trim);
+    let media_type = parts.next().filter(|t| t.contains('/'))?;
+
+    let quality = parts
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .map_or(Some(1.0), |(_, value)| value.trim().parse().ok())?;
+
+    Some((media_type, quality))
+}
+
+/// Quality of the most specific media range that matches the media type, 0 if none does.
+fn quality(ranges: &[(&str, f64)], media_type: &str) -> f64 {
+    let (main_type, _) = media_type.split_once('/').unwrap_or_default();
+
+    ranges
+        .iter()
+        .filter_map(|(range, quality)| {
+            let specificity = match range.split_once('/')? {
+                _ if range.eq_ignore_ascii_case(media_type) => 2,
+                (range_type, "*") if range_type.eq_ignore_ascii_case(main_type) => 1,
+                ("*", "*") => 0,
+                _ => return None,
+            };
+
+            Some((specificity, *quality))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map_or(0.0, |(_, quality)| quality)
+}
+
+/// Positions are `[longitude, latitude]` in WGS 84.
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point { coordinates: [f64; 2] },
+    LineString { coordinates: Vec<[f64; 2]> },
+    Polygon { coordinates: Vec<Vec<[f64; 2]>> },
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "type")]
+pub struct Feature {
+    #[schema(value_type = Object)]
+    pub id: Value,
+    /// `null` for entries without a geographic position.
+    pub geometry: Option<Geometry>,
+    #[schema(value_type = Object)]
+    pub properties: Value,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+impl FromIterator<Feature> for FeatureCollection {
+    fn from_iter<I: IntoIterator<Item = Feature>>(iter: I) -> Self {
+        Self {
+            features: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoResponse for FeatureCollection {
+    fn into_response(self) -> Response {
+        ([(CONTENT_TYPE, GEOJSON_CONTENT_TYPE)], Json(self)).into_response()
+    }
+}
+
+impl IntoResponse for Feature {
+    fn into_response(self) -> Response {
+        ([(CONTENT_TYPE, GEOJSON_CONTENT_TYPE)], Json(self)).into_response()
+    }
+}
+
+fn properties(value: &impl Serialize) -> Value {
+    let mut properties = serde_json::to_value(value).unwrap_or_default();
+
+    // The position is the geometry.
+    if let Some(properties) = properties.as_object_mut() {
+        properties.remove("lat");
+        properties.remove("lon");
+    }
+
+    properties
+}
+
+pub fn sensor_feature(sensor: &Sensor) -> Feature {
+    Feature {
+        id: sensor.id.into(),
+        geometry: sensor.has_global_position().then_some(Geometry::Point {
+            coordinates: [sensor.lon, sensor.lat],
+        }),
+        properties: properties(sensor),
+    }
+}
+
+/// The position of a device as a point, or its 1-sigma error ellipse as a polygon if it has
+/// one.
+pub fn trilateration_feature(
+    trilateration: &Trilateration,
+    georeference: Option<&Georeference>,
+    accuracy: bool,
+) -> Feature {
+    let geometry = match (accuracy, &trilateration.error_ellipse, georeference) {
+        (true, Some(ellipse), Some(georeference)) => {
+            Some(accuracy_polygon(trilateration, ellipse, georeference))
+        }
+        _ => trilateration
+            .lat
+            .zip(trilateration.lon)
+            .map(|(lat, lon)| Geometry::Point {
+                coordinates: [lon, lat],
+            }),
+    };
+
+    Feature {
+        id: trilateration.fingerprint.into(),
+        geometry,
+        properties: properties(trilateration),
+    }
+}
+
+fn accuracy_polygon(
+    trilateration: &Trilateration,
+    ellipse: &ErrorEllipse,
+    georeference: &Georeference,
+) -> Geometry {
+    let mut ring: Vec<[f64; 2]> = ellipse
+        .outline(trilateration.x, trilateration.y, ELLIPSE_POINTS)
+        .into_iter()
+        .map(|(x, y)| {
+            let (lat, lon) = georeference.local_to_global(x, y);
+            [lon, lat]
+        })
+        .collect();
+
+    // Exterior rings are counterclockwise, which the local axes may mirror.
+    let area: f64 = ring
+        .iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+        .sum();
+    if area < 0.0 {
+        ring.reverse();
+    }
+
+    ring.push(ring[0]);
+
+    Geometry::Polygon {
+        coordinates: vec![ring],
+    }
+}
+
+pub fn device_feature(device: &Device) -> Feature {
+    Feature {
+        id: device.fingerprint.into(),
+        geometry: device
+            .trilateration
+            .as_ref()
+            .and_then(|t| t.lat.zip(t.lon))
+            .map(|(lat, lon)| Geometry::Point {
+                coordinates: [lon, lat],
+            }),
+        properties: properties(device),
+    }
+}
+
+/// The successive positions of a device as a line, with their timestamps as a property.
+pub fn track_feature(fingerprint: u64, track: &[Trilateration]) -> Feature {
+    let (coordinates, timestamps): (Vec<[f64; 2]>, Vec<u64>) = track
+        .iter()
+        .filter_map(|t| Some(([t.lon?, t.lat?], t.timestamp)))
+        .unzip();
+
+    let geometry = match coordinates.len() {
+        0 => None,
+        1 => Some(Geometry::Point {
+            coordinates: coordinates[0],
+        }),
+        _ => Some(Geometry::LineString { coordinates }),
+    };
+
+    Feature {
+        id: fingerprint.into(),
+        geometry,
+        properties: serde_json::json!({
+            "fingerprint": fingerprint,
+            "timestamps": timestamps,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_geojson_only_with_a_higher_quality() {
+        assert!(prefers_geojson("application/geo+json"));
+        assert!(prefers_geojson("Application/GEO+JSON"));
+        assert!(prefers_geojson(
+            "application/json;q=0.5, application/geo+json"
+        ));
+        assert!(prefers_geojson(
+            "text/html, application/geo+json;q=0.9, */*;q=0.1"
+        ));
+
+        assert!(!prefers_geojson(""));
+        assert!(!prefers_geojson("*/*"));
+        assert!(!prefers_geojson("application/*"));
+        assert!(!prefers_geojson("application/json, application/geo+json"));
+        assert!(!prefers_geojson("application/geo+json;q=0"));
+        assert!(!prefers_geojson("application/geo+json; q=0.0, */*"));
+        assert!(!prefers_geojson(
+            "application/geo+json;q=0.4, application/*;q=0.8"
+        ));
+    }
+
+    #[test]
+    fn uses_the_most_specific_media_range() {
+        let ranges: Vec<_> = "*/*;q=0.1, application/*;q=0.5, application/geo+json;q=0.3"
+            .split(',')
+            .filter_map(media_range)
+            .collect();
+
+        assert_eq!(quality(&ranges, GEOJSON_CONTENT_TYPE), 0.3);
+        assert_eq!(quality(&ranges, "application/json"), 0.5);
+        assert_eq!(quality(&ranges, "text/html"), 0.1);
+        assert_eq!(quality(&[], "text/html"), 0.0);
+    }
+
+    #[test]
+    fn skips_malformed_media_ranges() {
+        assert_eq!(media_range("application/geo+json;q=abc"), None);
+        assert_eq!(media_range("geojson"), None);
+        assert_eq!(
+            media_range(" application/geo+json ; charset=utf-8 ; Q=0.7"),
+            Some(("application/geo+json", 0.7))
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_accept_to_vary() {
+        let response = ([(VARY, "origin")], "[]").into_response();
+
+        let response = vary_accept(response).await;
+
+        let vary: Vec<_> = response.headers().get_all(VARY).iter().collect();
+        assert_eq!(vary, ["origin", "accept"]);
+    }
+}
@@ -0,0 +1,87 @@
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use crate::AppState;
+use crate::coords::GeoreferenceReport;
+
+#[derive(Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LocalPosition {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GlobalPosition {
+    lat: f64,
+    lon: f64,
+}
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(index))
+        .routes(routes!(to_global))
+        .routes(routes!(to_local))
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "georeference",
+    responses((status = OK, body = GeoreferenceReport), (status = UNPROCESSABLE_ENTITY, description = "Not enough sensors with local and global coordinates"))
+)]
+async fn index(State(state): State<AppState>) -> impl IntoResponse {
+    match state.sensor_service.get_georeference().await {
+        Ok(georeference) => (StatusCode::OK, Json(georeference.report())).into_response(),
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/global",
+    tag = "georeference",
+    params(LocalPosition),
+    responses((status = OK, body = GlobalPosition), (status = UNPROCESSABLE_ENTITY, description = "Not enough sensors with local and global coordinates"))
+)]
+async fn to_global(
+    Query(position): Query<LocalPosition>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.sensor_service.get_georeference().await {
+        Ok(georeference) => {
+            let (lat, lon) = georeference.local_to_global(position.x, position.y);
+
+            (StatusCode::OK, Json(GlobalPosition { lat, lon })).into_response()
+        }
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/local",
+    tag = "georeference",
+    params(GlobalPosition),
+    responses((status = OK, body = LocalPosition), (status = UNPROCESSABLE_ENTITY, description = "Not enough sensors with local and global coordinates"))
+)]
+async fn to_local(
+    Query(position): Query<GlobalPosition>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.sensor_service.get_georeference().await {
+        Ok(georeference) => {
+            let (x, y) = georeference.global_to_local(position.lat, position.lon);
+
+            (StatusCode::OK, Json(LocalPosition { x, y })).into_response()
+        }
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+    }
+}
@@ -1,21 +1,69 @@
+use axum::Json;
 use axum::Router;
+use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::get;
+use utoipa::OpenApi;
+use utoipa::openapi::OpenApi as OpenApiDocument;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
+mod devices;
+mod geojson;
+mod georeference;
+mod query;
+mod references;
 mod sensors;
+mod sse;
 mod trilaterations;
 
 use crate::AppState;
+use crate::config::Config;
+use crate::events::Event;
 
-pub fn api(state: AppState) -> Router {
-    Router::new()
-        .route("/health", get(health))
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Sensor Backend API"),
+    servers((url = "/api")),
+    components(schemas(Event)),
+    tags(
+        (name = "sensors", description = "Sensor registry, calibration and packet statistics"),
+        (name = "trilaterations", description = "Positions of the devices"),
+        (name = "devices", description = "Pseudo-devices and their tracks"),
+        (name = "georeference", description = "Transform between local and global coordinates"),
+        (name = "references", description = "Reference transmitters for the path loss fit"),
+        (name = "system", description = "Health and configuration"),
+    )
+)]
+struct ApiDoc;
+
+/// The API router and its OpenAPI document, which is generated from the routes and serves them
+/// under `/api`.
+pub fn api(state: AppState) -> (Router, OpenApiDocument) {
+    let (router, openapi) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(health))
+        .routes(routes!(config))
+        .route("/devices.geojson", get(devices::index))
+        .route("/sensors.geojson", get(sensors::index))
+        .route("/trilaterations.geojson", get(trilaterations::index))
+        .nest("/devices", devices::routes())
+        .nest("/georeference", georeference::routes())
+        .nest("/references", references::routes())
         .nest("/sensors", sensors::routes())
         .nest("/trilaterations", trilaterations::routes())
-        .with_state(state)
+        .split_for_parts();
+
+    (router.with_state(state), openapi)
 }
 
+#[utoipa::path(get, path = "/health", tag = "system", responses((status = OK, body = String)))]
 async fn health() -> impl IntoResponse {
     (StatusCode::OK, "Healthy")
 }
+
+/// The effective configuration without the keys of the sensors.
+#[utoipa::path(get, path = "/config", tag = "system", responses((status = OK, body = Config)))]
+async fn config(State(state): State<AppState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(state.config.as_ref().clone()))
+}
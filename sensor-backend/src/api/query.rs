@@ -0,0 +1,455 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+pub const MAX_PAGE_SIZE: usize = 1000;
+/// Page size if no `limit` is given, so that a client has to follow the cursors to fetch
+/// everything.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Response header with the cursor of the next page, missing on the last page.
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// The paginated list a cursor belongs to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum List {
+    Sensors,
+    Trilaterations,
+}
+
+impl List {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(List::Sensors),
+            1 => Some(List::Trilaterations),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+pub fn check_limit(limit: Option<usize>) -> Result<(), String> {
+    match limit {
+        Some(limit) if !(1..=MAX_PAGE_SIZE).contains(&limit) => Err(format!(
+            "Limit must be between 1 and {}, got {}",
+            MAX_PAGE_SIZE, limit
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the value lies within the optional bounds.
+pub fn within(value: f64, min: Option<f64>, max: Option<f64>) -> bool {
+    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+}
+
+/// The leading bits of a fingerprint, given as hex digits optionally separated like a MAC
+/// address, e.g. `a4:83:e7`.
+#[derive(Clone, Copy)]
+pub struct FingerprintPrefix {
+    value: u64,
+    bits: u32,
+}
+
+impl FingerprintPrefix {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let digits: String = s.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+
+        if digits.len() > 16 {
+            return Err(format!("Fingerprint prefix {} is too long", s));
+        }
+
+        let value = match digits.is_empty() {
+            true => 0,
+            false => u64::from_str_radix(&digits, 16)
+                .map_err(|_| format!("Fingerprint prefix {} is not hex encoded", s))?,
+        };
+
+        Ok(Self {
+            value,
+            bits: digits.len() as u32 * 4,
+        })
+    }
+
+    pub fn matches(&self, fingerprint: u64) -> bool {
+        fingerprint.checked_shr(64 - self.bits).unwrap_or(0) == self.value
+    }
+}
+
+/// Position of the last item of a page in the sort order: its sort value and its ID, which
+/// breaks ties. It also records the list, sort key and order it was created for, the position
+/// is meaningless in any other.
+#[derive(Clone, Copy)]
+pub struct Cursor {
+    list: List,
+    sort: u8,
+    order: Order,
+    value: f64,
+    id: u64,
+}
+
+impl Cursor {
+    const SIZE: usize = 19;
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cursor {}", s);
+
+        let mut bytes = [0u8; Self::SIZE];
+        hex::decode_to_slice(s, &mut bytes).map_err(|_| invalid())?;
+
+        let list = List::from_u8(bytes[0]).ok_or_else(invalid)?;
+        let order = match bytes[2] {
+            0 => Order::Asc,
+            1 => Order::Desc,
+            _ => return Err(invalid()),
+        };
+        let (value, id) = bytes[3..].split_at(8);
+
+        Ok(Self {
+            list,
+            sort: bytes[1],
+            order,
+            value: f64::from_bits(u64::from_be_bytes(value.try_into().unwrap())),
+            id: u64::from_be_bytes(id.try_into().unwrap()),
+        })
+    }
+
+    fn encode(&self) -> String {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0] = self.list as u8;
+        bytes[1] = self.sort;
+        bytes[2] = self.order as u8;
+        bytes[3..11].copy_from_slice(&self.value.to_bits().to_be_bytes());
+        bytes[11..].copy_from_slice(&self.id.to_be_bytes());
+
+        hex::encode(bytes)
+    }
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// Sorts the items by the key, a sort value and a unique ID, and returns up to `limit` items,
+/// [`DEFAULT_PAGE_SIZE`] by default, following the cursor. Items that change between two
+/// requests are neither repeated nor skipped unless their sort value changes. `sort` identifies
+/// the sort key within the list, a cursor of another list, sort key or order is rejected.
+pub fn paginate<T>(
+    mut items: Vec<T>,
+    key: impl Fn(&T) -> (f64, u64),
+    list: List,
+    sort: u8,
+    order: Order,
+    cursor: Option<Cursor>,
+    limit: Option<usize>,
+) -> Result<Page<T>, String> {
+    let compare = |a: (f64, u64), b: (f64, u64)| {
+        let ordering = a.0.total_cmp(&b.0).then(a.1.cmp(&b.1));
+
+        match order {
+            Order::Asc => ordering,
+            Order::Desc => ordering.reverse(),
+        }
+    };
+
+    if let Some(cursor) = cursor {
+        if cursor.list != list || cursor.sort != sort || cursor.order != order {
+            return Err("Cursor belongs to another list, sort key or order".to_string());
+        }
+
+        items.retain(|item| compare(key(item), (cursor.value, cursor.id)).is_gt());
+    }
+    items.sort_by(|a, b| compare(key(a), key(b)));
+
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let next_cursor = (items.len() > limit).then(|| {
+        let (value, id) = key(&items[limit - 1]);
+
+        Cursor {
+            list,
+            sort,
+            order,
+            value,
+            id,
+        }
+        .encode()
+    });
+    items.truncate(limit);
+
+    Ok(Page { items, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sort value and ID, with a tie between 2 and 3.
+    const ITEMS: [(f64, u64); 5] = [(3.0, 1), (1.0, 2), (1.0, 3), (-2.5, 4), (f64::INFINITY, 5)];
+
+    fn ids(items: &[(f64, u64)]) -> Vec<u64> {
+        items.iter().map(|(_, id)| *id).collect()
+    }
+
+    /// Follows the cursors through all pages.
+    fn pages(order: Order, limit: usize) -> Vec<Vec<u64>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let page = paginate(
+                ITEMS.to_vec(),
+                |item| *item,
+                List::Sensors,
+                1,
+                order,
+                cursor,
+                Some(limit),
+            )
+            .unwrap();
+            pages.push(ids(&page.items));
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(Cursor::parse(&next).unwrap()),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn sorts_by_value_and_breaks_ties_by_id() {
+        let page = paginate(
+            ITEMS.to_vec(),
+            |item| *item,
+            List::Sensors,
+            0,
+            Order::Asc,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(ids(&page.items), [4, 2, 3, 1, 5]);
+        assert!(page.next_cursor.is_none());
+
+        let page = paginate(
+            ITEMS.to_vec(),
+            |item| *item,
+            List::Sensors,
+            0,
+            Order::Desc,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(ids(&page.items), [5, 1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn follows_the_cursor_through_all_pages() {
+        assert_eq!(pages(Order::Asc, 2), [vec![4, 2], vec![3, 1], vec![5]]);
+        assert_eq!(pages(Order::Desc, 2), [vec![5, 1], vec![3, 2], vec![4]]);
+        // The last page is full, but there is no next one.
+        assert_eq!(pages(Order::Asc, 5), [vec![4, 2, 3, 1, 5]]);
+    }
+
+    #[test]
+    fn neither_repeats_nor_skips_items_that_were_added_or_removed() {
+        let page = paginate(
+            ITEMS.to_vec(),
+            |item| *item,
+            List::Sensors,
+            0,
+            Order::Asc,
+            None,
+            Some(2),
+        )
+        .unwrap();
+        let cursor = Cursor::parse(&page.next_cursor.unwrap()).unwrap();
+
+        let mut items = ITEMS.to_vec();
+        items.retain(|(_, id)| *id != 3);
+        items.push((0.0, 6));
+        items.push((2.0, 7));
+
+        let page = paginate(
+            items,
+            |item| *item,
+            List::Sensors,
+            0,
+            Order::Asc,
+            Some(cursor),
+            None,
+        )
+        .unwrap();
+        assert_eq!(ids(&page.items), [7, 1, 5]);
+    }
+
+    #[test]
+    fn rejects_cursors_of_another_sort_or_order() {
+        let page = paginate(
+            ITEMS.to_vec(),
+            |item| *item,
+            List::Sensors,
+            1,
+            Order::Asc,
+            None,
+            Some(1),
+        )
+        .unwrap();
+        let cursor = Cursor::parse(&page.next_cursor.unwrap()).unwrap();
+
+        assert!(
+            paginate(
+                ITEMS.to_vec(),
+                |item| *item,
+                List::Sensors,
+                2,
+                Order::Asc,
+                Some(cursor),
+                None
+            )
+            .is_err()
+        );
+        assert!(
+            paginate(
+                ITEMS.to_vec(),
+                |item| *item,
+                List::Sensors,
+                1,
+                Order::Desc,
+                Some(cursor),
+                None
+            )
+            .is_err()
+        );
+        assert!(
+            paginate(
+                ITEMS.to_vec(),
+                |item| *item,
+                List::Sensors,
+                1,
+                Order::Asc,
+                Some(cursor),
+                None
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_cursors_of_another_list() {
+        let page = paginate(
+            ITEMS.to_vec(),
+            |item| *item,
+            List::Sensors,
+            0,
+            Order::Asc,
+            None,
+            Some(1),
+        )
+        .unwrap();
+        let cursor = Cursor::parse(&page.next_cursor.unwrap()).unwrap();
+
+        let page = paginate(
+            ITEMS.to_vec(),
+            |item| *item,
+            List::Trilaterations,
+            0,
+            Order::Asc,
+            Some(cursor),
+            None,
+        );
+        assert!(page.is_err());
+    }
+
+    #[test]
+    fn limits_pages_without_a_limit() {
+        let items: Vec<(f64, u64)> = (0..DEFAULT_PAGE_SIZE as u64 + 1)
+            .map(|id| (0.0, id))
+            .collect();
+
+        let page = paginate(
+            items,
+            |item| *item,
+            List::Trilaterations,
+            0,
+            Order::Asc,
+            None,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(page.items.len(), DEFAULT_PAGE_SIZE);
+        let cursor = Cursor::parse(&page.next_cursor.unwrap()).unwrap();
+        assert_eq!(cursor.id, DEFAULT_PAGE_SIZE as u64 - 1);
+    }
+
+    #[test]
+    fn round_trips_cursors() {
+        for (list, sort, order, value, id) in [
+            (List::Sensors, 0, Order::Asc, 0.0, 0),
+            (List::Trilaterations, 3, Order::Desc, -12.75, u64::MAX),
+            (List::Sensors, 5, Order::Asc, f64::INFINITY, 11256099),
+        ] {
+            let cursor = Cursor::parse(
+                &Cursor {
+                    list,
+                    sort,
+                    order,
+                    value,
+                    id,
+                }
+                .encode(),
+            )
+            .unwrap();
+
+            assert_eq!(cursor.list, list);
+            assert_eq!(cursor.sort, sort);
+            assert!(cursor.order == order);
+            assert_eq!(cursor.value.to_bits(), value.to_bits());
+            assert_eq!(cursor.id, id);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        assert!(Cursor::parse("").is_err());
+        assert!(Cursor::parse("zz").is_err());
+        // Order 2 and list 2 do not exist.
+        assert!(Cursor::parse(&format!("000102{}", "00".repeat(16))).is_err());
+        assert!(Cursor::parse(&format!("020101{}", "00".repeat(16))).is_err());
+        assert!(Cursor::parse(&format!("010101{}", "00".repeat(16))).is_ok());
+    }
+
+    #[test]
+    fn matches_fingerprint_prefixes() {
+        let prefix = FingerprintPrefix::parse("a4:83:e7").unwrap();
+        assert!(prefix.matches(0xa483_e7ff_ffff_ffff));
+        assert!(prefix.matches(0xa483_e700_0000_0000));
+        assert!(!prefix.matches(0xa483_e800_0000_0000));
+
+        let odd = FingerprintPrefix::parse("a4-8").unwrap();
+        assert!(odd.matches(0xa48f_0000_0000_0000));
+        assert!(!odd.matches(0xa470_0000_0000_0000));
+    }
+
+    #[test]
+    fn matches_empty_and_full_fingerprint_prefixes() {
+        let empty = FingerprintPrefix::parse("").unwrap();
+        assert!(empty.matches(0));
+        assert!(empty.matches(u64::MAX));
+
+        let full = FingerprintPrefix::parse("0123456789abcdef").unwrap();
+        assert!(full.matches(0x0123_4567_89ab_cdef));
+        assert!(!full.matches(0x0123_4567_89ab_cdee));
+
+        assert!(FingerprintPrefix::parse("0123456789abcdef0").is_err());
+        assert!(FingerprintPrefix::parse("g0").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use crate::AppState;
+use crate::path_loss::ReferenceTransmitter;
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(index, create))
+        .routes(routes!(remove))
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "references",
+    responses((status = OK, body = Vec<ReferenceTransmitter>))
+)]
+async fn index(State(state): State<AppState>) -> impl IntoResponse {
+    let references = state.sensor_service.get_references().await;
+
+    (StatusCode::OK, Json(references))
+}
+
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "references",
+    request_body = ReferenceTransmitter,
+    responses((status = CREATED, body = ReferenceTransmitter))
+)]
+async fn create(
+    State(state): State<AppState>,
+    Json(reference): Json<ReferenceTransmitter>,
+) -> impl IntoResponse {
+    state.sensor_service.add_reference(reference.clone()).await;
+
+    (StatusCode::CREATED, Json(reference))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{fingerprint}",
+    tag = "references",
+    params(("fingerprint" = u64, Path, description = "Fingerprint of the reference transmitter")),
+    responses(
+        (status = OK, body = ReferenceTransmitter),
+        (status = NOT_FOUND, description = "Reference transmitter not found"),
+    )
+)]
+async fn remove(Path(fingerprint): Path<u64>, State(state): State<AppState>) -> impl IntoResponse {
+    match state.sensor_service.remove_reference(fingerprint).await {
+        Some(reference) => (StatusCode::OK, Json(reference)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Reference transmitter not found").into_response(),
+    }
+}
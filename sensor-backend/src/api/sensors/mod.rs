@@ -1,50 +1,501 @@
-use std::time::Duration;
+use std::collections::HashMap;
 
-use axum::extract::State;
+use axum::Json;
 use axum::extract::ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::map_response;
 use axum::response::IntoResponse;
-use axum::routing::get;
-use axum::{Json, Router};
-use tokio::time::interval;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::error;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
 use crate::AppState;
+use crate::api::geojson::{
+    FeatureCollection, Format, GEOJSON_CONTENT_TYPE, sensor_feature, vary_accept,
+};
+use crate::api::query::{Cursor, List, NEXT_CURSOR_HEADER, Order, check_limit, paginate, within};
+use crate::api::sse::{event_stream, last_event_id};
+use crate::auth::RejectionStats;
+use crate::calibration::{CalibrationSession, DEFAULT_CALIBRATION_SAMPLES};
+use crate::events::{Event, SequencedEvent};
+use crate::registry::{SensorPatch, SensorRegistration};
+use crate::sensors::Sensor;
+use crate::stats::PacketStats;
 
-pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route("/", get(index))
-        .route("/ws", get(ws_handler))
+#[derive(Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    #[default]
+    Id,
+    X,
+    Y,
 }
 
-async fn index(State(state): State<AppState>) -> impl IntoResponse {
-    let sensors = state.sensor_service.get_sensors().await;
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct ListQuery {
+    /// Only sensors that sent a frame since then.
+    since: Option<u64>,
+    min_x: Option<f64>,
+    max_x: Option<f64>,
+    min_y: Option<f64>,
+    max_y: Option<f64>,
+    min_lat: Option<f64>,
+    max_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lon: Option<f64>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: Order,
+    /// Page size up to 1000, 100 by default.
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+impl ListQuery {
+    fn matches(&self, sensor: &Sensor, last_seen: &HashMap<u8, u64>) -> bool {
+        let geographic = [self.min_lat, self.max_lat, self.min_lon, self.max_lon]
+            .iter()
+            .any(Option::is_some);
+
+        self.since
+            .is_none_or(|since| last_seen.get(&sensor.id).is_some_and(|t| *t >= since))
+            && within(sensor.x, self.min_x, self.max_x)
+            && within(sensor.y, self.min_y, self.max_y)
+            && (!geographic
+                || sensor.has_global_position()
+                    && within(sensor.lat, self.min_lat, self.max_lat)
+                    && within(sensor.lon, self.min_lon, self.max_lon))
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CalibrationValues {
+    rssi_at_1m: f64,
+    rssi_offset: f64,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CalibrationRequest {
+    fingerprint: u64,
+    distance: f64,
+    samples: Option<usize>,
+}
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    // The route layer only applies to the routes added before it.
+    OpenApiRouter::new()
+        .routes(routes!(index))
+        .route_layer(map_response(vary_accept))
+        .routes(routes!(ws_handler))
+        .routes(routes!(sse_handler))
+        .routes(routes!(stats))
+        .routes(routes!(rejections))
+        .routes(routes!(
+            sensor,
+            create_sensor,
+            replace_sensor,
+            update_sensor,
+            remove_sensor
+        ))
+        .routes(routes!(set_calibration))
+        .routes(routes!(
+            get_calibration_session,
+            start_calibration_session,
+            cancel_calibration_session
+        ))
+}
+
+/// The sensors, also as GeoJSON at `/sensors.geojson`.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "sensors",
+    params(ListQuery),
+    responses(
+        (status = OK, content(
+            (Vec<Sensor> = "application/json"),
+            (FeatureCollection = GEOJSON_CONTENT_TYPE),
+        )),
+        (status = BAD_REQUEST, description = "Invalid cursor or limit"),
+    )
+)]
+pub(super) async fn index(
+    format: Format,
+    Query(query): Query<ListQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let cursor = match query.cursor.as_deref().map(Cursor::parse).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Err(e) = check_limit(query.limit) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let last_seen: HashMap<u8, u64> = state
+        .sensor_service
+        .get_packet_stats()
+        .await
+        .into_iter()
+        .map(|stats| (stats.sensor_id, stats.last_seen))
+        .collect();
+
+    let sensors: Vec<Sensor> = state
+        .sensor_service
+        .get_sensors()
+        .await
+        .into_iter()
+        .filter(|sensor| query.matches(sensor, &last_seen))
+        .collect();
+
+    let page = paginate(
+        sensors,
+        |sensor| {
+            let value = match query.sort {
+                SortKey::Id => 0.0,
+                SortKey::X => sensor.x,
+                SortKey::Y => sensor.y,
+            };
+
+            (value, sensor.id as u64)
+        },
+        List::Sensors,
+        query.sort as u8,
+        query.order,
+        cursor,
+        query.limit,
+    );
+    let page = match page {
+        Ok(page) => page,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let body = match format {
+        Format::Json => Json(page.items).into_response(),
+        Format::GeoJson => page
+            .items
+            .iter()
+            .map(sensor_feature)
+            .collect::<FeatureCollection>()
+            .into_response(),
+    };
+
+    match page.next_cursor {
+        Some(cursor) => (StatusCode::OK, [(NEXT_CURSOR_HEADER, cursor)], body).into_response(),
+        None => (StatusCode::OK, body).into_response(),
+    }
+}
+
+#[utoipa::path(get, path = "/stats", tag = "sensors", responses((status = OK, body = Vec<PacketStats>)))]
+async fn stats(State(state): State<AppState>) -> impl IntoResponse {
+    let stats = state.sensor_service.get_packet_stats().await;
+
+    (StatusCode::OK, Json(stats))
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/rejections",
+    tag = "sensors",
+    responses((status = OK, body = RejectionStats))
+)]
+async fn rejections(State(state): State<AppState>) -> impl IntoResponse {
+    let rejections = state.sensor_service.get_rejection_stats().await;
+
+    (StatusCode::OK, Json(rejections))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    responses((status = OK, body = Sensor), (status = NOT_FOUND, description = "Sensor not found"))
+)]
+async fn sensor(Path(id): Path<u8>, State(state): State<AppState>) -> impl IntoResponse {
+    match state.sensor_service.get_sensor(id).await {
+        Some(sensor) => (StatusCode::OK, Json(sensor)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Sensor not found").into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    request_body = SensorRegistration,
+    responses(
+        (status = CREATED, body = Sensor),
+        (status = BAD_REQUEST, description = "Invalid registration"),
+        (status = CONFLICT, description = "Sensor already exists"),
+    )
+)]
+async fn create_sensor(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+    Json(registration): Json<SensorRegistration>,
+) -> impl IntoResponse {
+    if let Err(e) = registration.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    match state.sensor_service.create_sensor(id, registration).await {
+        Some(sensor) => (StatusCode::CREATED, Json(sensor)).into_response(),
+        None => (StatusCode::CONFLICT, "Sensor already exists").into_response(),
+    }
+}
+
+/// Registers the sensor or replaces its registration, the calibration is kept.
+#[utoipa::path(
+    put,
+    path = "/{id}",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    request_body = SensorRegistration,
+    responses(
+        (status = OK, body = Sensor),
+        (status = CREATED, body = Sensor),
+        (status = BAD_REQUEST, description = "Invalid registration"),
+    )
+)]
+async fn replace_sensor(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+    Json(registration): Json<SensorRegistration>,
+) -> impl IntoResponse {
+    if let Err(e) = registration.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    match state.sensor_service.replace_sensor(id, registration).await {
+        (sensor, true) => (StatusCode::CREATED, Json(sensor)).into_response(),
+        (sensor, false) => (StatusCode::OK, Json(sensor)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/{id}",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    request_body = SensorPatch,
+    responses((status = OK, body = Sensor), (status = BAD_REQUEST, description = "Invalid registration"), (status = NOT_FOUND, description = "Sensor not found"))
+)]
+async fn update_sensor(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+    Json(patch): Json<SensorPatch>,
+) -> impl IntoResponse {
+    if let Err(e) = patch.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    match state.sensor_service.update_sensor(id, patch).await {
+        Some(sensor) => (StatusCode::OK, Json(sensor)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Sensor not found").into_response(),
+    }
+}
+
+/// Removes the sensor with its calibration, statistics and pending measurements. The replay
+/// protection of its key is kept. With the `accept` packet position policy, the next new
+/// `Hello` or `Heartbeat` of the sensor registers it again, disable it to keep it out instead.
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    responses((status = OK, body = Sensor), (status = NOT_FOUND, description = "Sensor not found"))
+)]
+async fn remove_sensor(Path(id): Path<u8>, State(state): State<AppState>) -> impl IntoResponse {
+    match state.sensor_service.remove_sensor(id).await {
+        Some(sensor) => (StatusCode::OK, Json(sensor)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Sensor not found").into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/calibration",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    request_body = CalibrationValues,
+    responses((status = OK, body = Sensor), (status = NOT_FOUND, description = "Sensor not found"))
+)]
+async fn set_calibration(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+    Json(values): Json<CalibrationValues>,
+) -> impl IntoResponse {
+    match state
+        .sensor_service
+        .set_calibration(id, values.rssi_at_1m, values.rssi_offset)
+        .await
+    {
+        Some(sensor) => (StatusCode::OK, Json(sensor)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Sensor not found").into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/calibration/session",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    request_body = CalibrationRequest,
+    responses(
+        (status = CREATED, body = CalibrationSession),
+        (status = BAD_REQUEST, description = "Invalid distance or samples"),
+        (status = NOT_FOUND, description = "Sensor not found"),
+    )
+)]
+async fn start_calibration_session(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+    Json(request): Json<CalibrationRequest>,
+) -> impl IntoResponse {
+    if !(request.distance.is_finite() && request.distance > 0.0) {
+        return (StatusCode::BAD_REQUEST, "Distance must be positive").into_response();
+    }
+
+    let samples = request.samples.unwrap_or(DEFAULT_CALIBRATION_SAMPLES);
+    if samples == 0 {
+        return (StatusCode::BAD_REQUEST, "Samples must be positive").into_response();
+    }
+
+    match state
+        .sensor_service
+        .start_calibration(id, request.fingerprint, request.distance, samples)
+        .await
+    {
+        Some(session) => (StatusCode::CREATED, Json(session)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Sensor not found").into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/calibration/session",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    responses((status = OK, body = CalibrationSession), (status = NOT_FOUND, description = "Calibration session not found"))
+)]
+async fn get_calibration_session(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.sensor_service.get_calibration(id).await {
+        Some(session) => (StatusCode::OK, Json(session)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Calibration session not found").into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{id}/calibration/session",
+    tag = "sensors",
+    params(("id" = u8, Path, description = "ID of the sensor")),
+    responses((status = OK, body = CalibrationSession), (status = NOT_FOUND, description = "Calibration session not found"))
+)]
+async fn cancel_calibration_session(
+    Path(id): Path<u8>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.sensor_service.cancel_calibration(id).await {
+        Some(session) => (StatusCode::OK, Json(session)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Calibration session not found").into_response(),
+    }
+}
+
+fn is_sensor_event(event: &Event) -> bool {
+    matches!(event, Event::SensorUpdated(_) | Event::SensorRemoved { .. })
+}
+
+/// A `snapshot` of the sensors followed by `sensor_updated` and `sensor_removed` events.
+#[utoipa::path(
+    get,
+    path = "/sse",
+    tag = "sensors",
+    params(("Last-Event-ID" = Option<String>, Header, description = "ID of the last received event")),
+    responses((status = OK, description = "Stream of events", content_type = "text/event-stream"))
+)]
+async fn sse_handler(headers: HeaderMap, State(state): State<AppState>) -> impl IntoResponse {
+    let subscription = state.sensor_service.subscribe(last_event_id(&headers));
+    let snapshot = match subscription.missed {
+        Some(_) => None,
+        None => Some(json!(state.sensor_service.get_sensors().await)),
+    };
 
-    (StatusCode::OK, Json(sensors))
+    event_stream(
+        state.sensor_service,
+        subscription,
+        snapshot,
+        is_sensor_event,
+    )
 }
 
+/// A `snapshot` of the sensors followed by `sensor_updated` and `sensor_removed` events.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "sensors",
+    responses((status = SWITCHING_PROTOCOLS, description = "WebSocket of events"))
+)]
 async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
     ws.on_upgrade(|socket| handle_socket(socket, state))
 }
 
+/// Sends a snapshot of the sensors on connect and every change afterwards.
 async fn handle_socket(mut socket: WebSocket, state: AppState) {
-    let mut interval = interval(Duration::from_secs(5));
+    let mut events = state.sensor_service.subscribe(None).receiver;
+    let mut send_snapshot = true;
 
     loop {
+        if send_snapshot {
+            let sensors = state.sensor_service.get_sensors().await;
+
+            let snapshot = json!({ "type": "snapshot", "data": sensors });
+            if let Err(e) = socket
+                .send(Message::Text(snapshot.to_string().into()))
+                .await
+            {
+                error!("Failed to send sensors: {}", e);
+                break;
+            }
+
+            send_snapshot = false;
+        }
+
         tokio::select! {
-            _ = interval.tick() => {
-                let sensors = state.sensor_service.get_sensors().await;
+            event = events.recv() => {
+                let event = match event {
+                    Ok(SequencedEvent { event, .. }) if is_sensor_event(&event) => event,
+                    Ok(_) => continue,
+                    // Missed events are replaced by a fresh snapshot.
+                    Err(RecvError::Lagged(_)) => {
+                        send_snapshot = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
 
-                let data = match serde_json::to_string(&sensors) {
+                let data = match serde_json::to_string(&event) {
                     Ok(json) => Utf8Bytes::from(json),
                     Err(e) => {
-                        error!("Failed to serialize sensors: {}", e);
+                        error!("Failed to serialize event: {}", e);
                         continue;
                     }
                 };
 
                 if let Err(e) = socket.send(Message::Text(data)).await {
-                    error!("Failed to send sensors: {}", e);
+                    error!("Failed to send event: {}", e);
                     break;
                 }
             }
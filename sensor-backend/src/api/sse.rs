@@ -0,0 +1,87 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use futures_util::{Stream, StreamExt, stream};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::error;
+
+use crate::events::{Event, SequencedEvent, Subscription};
+use crate::sensors::SensorService;
+
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+pub fn last_event_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Streams the selected events of the subscription, preceded by the snapshot that is required
+/// if the events after the `Last-Event-ID` of the client are no longer available. A client that
+/// lags behind is disconnected and resumes after its last event when it reconnects.
+pub fn event_stream(
+    sensor_service: Arc<SensorService>,
+    subscription: Subscription,
+    snapshot: Option<Value>,
+    filter: fn(&Event) -> bool,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let Subscription {
+        receiver,
+        missed,
+        last_sequence,
+    } = subscription;
+
+    let initial: Vec<SseEvent> = match (missed, snapshot) {
+        (Some(missed), _) => missed
+            .iter()
+            .filter(|e| filter(&e.event))
+            .filter_map(|e| sse_event(&sensor_service, e))
+            .collect(),
+        (None, Some(snapshot)) => SseEvent::default()
+            .id(sensor_service.event_id(last_sequence))
+            .event("snapshot")
+            .json_data(snapshot)
+            .inspect_err(|e| error!("Failed to serialize snapshot: {}", e))
+            .into_iter()
+            .collect(),
+        (None, None) => Vec::new(),
+    };
+
+    let events = stream::unfold(receiver, move |mut receiver| {
+        let sensor_service = sensor_service.clone();
+
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if filter(&event.event) => {
+                        if let Some(event) = sse_event(&sensor_service, &event) {
+                            return Some((event, receiver));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(_) | RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    Sse::new(stream::iter(initial).chain(events).map(Ok)).keep_alive(KeepAlive::default())
+}
+
+fn sse_event(sensor_service: &SensorService, event: &SequencedEvent) -> Option<SseEvent> {
+    // The name of the event is the type, the data is the content of the WebSocket message.
+    let data = serde_json::to_value(&event.event)
+        .map(|mut value| value["data"].take())
+        .inspect_err(|e| error!("Failed to serialize event: {}", e))
+        .ok()?;
+
+    SseEvent::default()
+        .id(sensor_service.event_id(event.sequence))
+        .event(event.event.name())
+        .json_data(data)
+        .inspect_err(|e| error!("Failed to serialize event: {}", e))
+        .ok()
+}
@@ -1,50 +1,277 @@
-use std::time::Duration;
-
-use axum::extract::State;
+use axum::Json;
 use axum::extract::ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
-use axum::http::StatusCode;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::map_response;
 use axum::response::IntoResponse;
-use axum::routing::get;
-use axum::{Json, Router};
-use tokio::time::interval;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::error;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
 use crate::AppState;
+use crate::api::geojson::{
+    FeatureCollection, Format, GEOJSON_CONTENT_TYPE, trilateration_feature, vary_accept,
+};
+use crate::api::query::{
+    Cursor, FingerprintPrefix, List, NEXT_CURSOR_HEADER, Order, check_limit, paginate, within,
+};
+use crate::api::sse::{event_stream, last_event_id};
+use crate::events::{Event, SequencedEvent};
+use crate::sensors::Trilateration;
+
+#[derive(Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    #[default]
+    Fingerprint,
+    Timestamp,
+    Residual,
+    Error,
+    Sensors,
+    Samples,
+}
 
-pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route("/", get(index))
-        .route("/ws", get(ws_handler))
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct ListQuery {
+    since: Option<u64>,
+    min_x: Option<f64>,
+    max_x: Option<f64>,
+    min_y: Option<f64>,
+    max_y: Option<f64>,
+    min_lat: Option<f64>,
+    max_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lon: Option<f64>,
+    min_sensors: Option<usize>,
+    max_residual: Option<f64>,
+    /// Largest semi-major axis of the error ellipse in meters.
+    max_error: Option<f64>,
+    fingerprint_prefix: Option<String>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: Order,
+    /// Page size up to 1000, 100 by default.
+    limit: Option<usize>,
+    cursor: Option<String>,
+    /// Error ellipses instead of points in GeoJSON.
+    #[serde(default)]
+    accuracy: bool,
 }
 
-async fn index(State(state): State<AppState>) -> impl IntoResponse {
-    let trilaterations = state.sensor_service.get_trilaterations().await;
+impl ListQuery {
+    fn matches(&self, t: &Trilateration, prefix: Option<FingerprintPrefix>) -> bool {
+        let geographic = [self.min_lat, self.max_lat, self.min_lon, self.max_lon]
+            .iter()
+            .any(Option::is_some);
+
+        self.since.is_none_or(|since| t.timestamp >= since)
+            && within(t.x, self.min_x, self.max_x)
+            && within(t.y, self.min_y, self.max_y)
+            && (!geographic
+                || t.lat.zip(t.lon).is_some_and(|(lat, lon)| {
+                    within(lat, self.min_lat, self.max_lat)
+                        && within(lon, self.min_lon, self.max_lon)
+                }))
+            && self.min_sensors.is_none_or(|min| t.sensors >= min)
+            && self.max_residual.is_none_or(|max| t.residual <= max)
+            && self.max_error.is_none_or(|max| {
+                t.error_ellipse
+                    .is_some_and(|ellipse| ellipse.semi_major <= max)
+            })
+            && prefix.is_none_or(|prefix| prefix.matches(t.fingerprint))
+    }
+}
 
-    (StatusCode::OK, Json(trilaterations))
+pub fn routes() -> OpenApiRouter<AppState> {
+    // The route layer only applies to the routes added before it.
+    OpenApiRouter::new()
+        .routes(routes!(index))
+        .route_layer(map_response(vary_accept))
+        .routes(routes!(ws_handler))
+        .routes(routes!(sse_handler))
 }
 
+/// The latest position of every device, also as GeoJSON at `/trilaterations.geojson`.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "trilaterations",
+    params(ListQuery),
+    responses(
+        (status = OK, content(
+            (Vec<Trilateration> = "application/json"),
+            (FeatureCollection = GEOJSON_CONTENT_TYPE),
+        )),
+        (status = BAD_REQUEST, description = "Invalid filter, cursor or limit"),
+    )
+)]
+pub(super) async fn index(
+    format: Format,
+    Query(query): Query<ListQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let prefix = match query
+        .fingerprint_prefix
+        .as_deref()
+        .map(FingerprintPrefix::parse)
+        .transpose()
+    {
+        Ok(prefix) => prefix,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let cursor = match query.cursor.as_deref().map(Cursor::parse).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Err(e) = check_limit(query.limit) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let trilaterations: Vec<Trilateration> = state
+        .sensor_service
+        .get_trilaterations()
+        .await
+        .into_iter()
+        .filter(|t| query.matches(t, prefix))
+        .collect();
+
+    let page = paginate(
+        trilaterations,
+        |t| {
+            let value = match query.sort {
+                SortKey::Fingerprint => 0.0,
+                SortKey::Timestamp => t.timestamp as f64,
+                SortKey::Residual => t.residual,
+                SortKey::Error => t
+                    .error_ellipse
+                    .map_or(f64::INFINITY, |ellipse| ellipse.semi_major),
+                SortKey::Sensors => t.sensors as f64,
+                SortKey::Samples => t.samples as f64,
+            };
+
+            (value, t.fingerprint)
+        },
+        List::Trilaterations,
+        query.sort as u8,
+        query.order,
+        cursor,
+        query.limit,
+    );
+    let page = match page {
+        Ok(page) => page,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let body = match format {
+        Format::Json => Json(page.items).into_response(),
+        Format::GeoJson => {
+            let georeference = state.sensor_service.get_georeference().await.ok();
+
+            page.items
+                .iter()
+                .map(|t| trilateration_feature(t, georeference.as_ref(), query.accuracy))
+                .collect::<FeatureCollection>()
+                .into_response()
+        }
+    };
+
+    match page.next_cursor {
+        Some(cursor) => (StatusCode::OK, [(NEXT_CURSOR_HEADER, cursor)], body).into_response(),
+        None => (StatusCode::OK, body).into_response(),
+    }
+}
+
+fn is_trilateration_event(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::PositionUpdated(_) | Event::DeviceExpired { .. }
+    )
+}
+
+/// A `snapshot` of the positions followed by `position_updated` and `device_expired` events.
+#[utoipa::path(
+    get,
+    path = "/sse",
+    tag = "trilaterations",
+    params(("Last-Event-ID" = Option<String>, Header, description = "ID of the last received event")),
+    responses((status = OK, description = "Stream of events", content_type = "text/event-stream"))
+)]
+async fn sse_handler(headers: HeaderMap, State(state): State<AppState>) -> impl IntoResponse {
+    let subscription = state.sensor_service.subscribe(last_event_id(&headers));
+    let snapshot = match subscription.missed {
+        Some(_) => None,
+        None => Some(json!(state.sensor_service.get_trilaterations().await)),
+    };
+
+    event_stream(
+        state.sensor_service,
+        subscription,
+        snapshot,
+        is_trilateration_event,
+    )
+}
+
+/// A `snapshot` of the positions followed by `position_updated` and `device_expired` events.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "trilaterations",
+    responses((status = SWITCHING_PROTOCOLS, description = "WebSocket of events"))
+)]
 async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
     ws.on_upgrade(|socket| handle_socket(socket, state))
 }
 
+/// Sends a snapshot of the trilaterations on connect and every change afterwards.
 async fn handle_socket(mut socket: WebSocket, state: AppState) {
-    let mut interval = interval(Duration::from_secs(5));
+    let mut events = state.sensor_service.subscribe(None).receiver;
+    let mut send_snapshot = true;
 
     loop {
+        if send_snapshot {
+            let trilaterations = state.sensor_service.get_trilaterations().await;
+
+            let snapshot = json!({ "type": "snapshot", "data": trilaterations });
+            if let Err(e) = socket
+                .send(Message::Text(snapshot.to_string().into()))
+                .await
+            {
+                error!("Failed to send trilaterations: {}", e);
+                break;
+            }
+
+            send_snapshot = false;
+        }
+
         tokio::select! {
-            _ = interval.tick() => {
-                let trilaterations = state.sensor_service.get_trilaterations().await;
+            event = events.recv() => {
+                let event = match event {
+                    Ok(SequencedEvent { event, .. }) if is_trilateration_event(&event) => event,
+                    Ok(_) => continue,
+                    // Missed events are replaced by a fresh snapshot.
+                    Err(RecvError::Lagged(_)) => {
+                        send_snapshot = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
 
-                let data = match serde_json::to_string(&trilaterations) {
+                let data = match serde_json::to_string(&event) {
                     Ok(json) => Utf8Bytes::from(json),
                     Err(e) => {
-                        error!("Failed to serialize trilateration: {}", e);
+                        error!("Failed to serialize event: {}", e);
                         continue;
                     }
                 };
 
                 if let Err(e) = socket.send(Message::Text(data)).await {
-                    error!("Failed to send trilateration: {}", e);
+                    error!("Failed to send event: {}", e);
                     break;
                 }
             }
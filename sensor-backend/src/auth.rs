@@ -0,0 +1,126 @@
+use std::str::FromStr;
+
+use sensor_lib::protocol::{FrameError, SensorKey, parse_key};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Number of counters below the highest accepted one that are still accepted once, so that
+/// reordered frames are not dropped.
+const REPLAY_WINDOW_SIZE: u64 = 64;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub keys: Vec<SensorKeyConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SensorKeyConfig {
+    pub sensor_id: u8,
+    /// Hex encoded, never serialized so that the keys do not leak through the config endpoint.
+    #[serde(skip_serializing, deserialize_with = "deserialize_key")]
+    pub key: SensorKey,
+}
+
+/// Parses `<sensor ID>:<hex encoded key>` as given on the command line.
+impl FromStr for SensorKeyConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sensor_id, key) = s
+            .split_once(':')
+            .ok_or("expected <sensor ID>:<hex encoded key>")?;
+
+        Ok(Self {
+            sensor_id: sensor_id.parse().map_err(|e| format!("{}", e))?,
+            key: parse_key(key).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+fn deserialize_key<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SensorKey, D::Error> {
+    let key = String::deserialize(deserializer)?;
+
+    parse_key(&key).map_err(serde::de::Error::custom)
+}
+
+/// Sliding window over the frame counters of a sensor, rejecting every counter seen before.
+pub struct ReplayWindow {
+    highest: u64,
+    /// Bit `n` is set if the counter `highest - n` was accepted.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    pub fn new(counter: u64) -> Self {
+        Self {
+            highest: counter,
+            seen: 1,
+        }
+    }
+
+    /// Continues after the highest counter accepted before a restart. Which counters below it
+    /// were seen is not known, so all of them are rejected.
+    pub fn restore(highest: u64) -> Self {
+        Self {
+            highest,
+            seen: u64::MAX,
+        }
+    }
+
+    pub fn highest(&self) -> u64 {
+        self.highest
+    }
+
+    /// Accepts the counter if it was not seen before and is not too old to tell.
+    pub fn accept(&mut self, counter: u64) -> bool {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+
+            self.seen = if shift >= REPLAY_WINDOW_SIZE {
+                1
+            } else {
+                self.seen << shift | 1
+            };
+            self.highest = counter;
+
+            return true;
+        }
+
+        let offset = self.highest - counter;
+        if offset >= REPLAY_WINDOW_SIZE || self.seen & 1 << offset != 0 {
+            return false;
+        }
+
+        self.seen |= 1 << offset;
+
+        true
+    }
+}
+
+/// Frames dropped by the listener, by reason.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RejectionStats {
+    pub malformed: u64,
+    pub unsupported_version: u64,
+    pub unknown_sensor: u64,
+    pub invalid_tag: u64,
+    pub replayed: u64,
+}
+
+impl RejectionStats {
+    pub fn record(&mut self, error: &FrameError) {
+        match error {
+            FrameError::TooShort
+            | FrameError::InvalidMagic
+            | FrameError::Malformed
+            | FrameError::BufferTooSmall => self.malformed += 1,
+            FrameError::UnsupportedVersion(_) => self.unsupported_version += 1,
+            FrameError::UnknownSensor(_) => self.unknown_sensor += 1,
+            FrameError::InvalidTag => self.invalid_tag += 1,
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::rssi::{calculate_reference_rssi, median};
+
+pub const DEFAULT_CALIBRATION_SAMPLES: usize = 30;
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct CalibrationSession {
+    pub sensor_id: u8,
+    pub fingerprint: u64,
+    pub distance: f64,
+    pub target_samples: usize,
+    pub captured_samples: usize,
+    pub rssi_at_1m: Option<f64>,
+    #[serde(skip)]
+    samples: Vec<f64>,
+}
+
+impl CalibrationSession {
+    pub fn new(sensor_id: u8, fingerprint: u64, distance: f64, target_samples: usize) -> Self {
+        Self {
+            sensor_id,
+            fingerprint,
+            distance,
+            target_samples,
+            captured_samples: 0,
+            rssi_at_1m: None,
+            samples: Vec::with_capacity(target_samples),
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.rssi_at_1m.is_some()
+    }
+
+    /// Captures a sample of the calibration device and returns the reference RSSI at 1m once
+    /// enough samples have been captured.
+    pub fn add_sample(&mut self, rssi: f64, path_loss_exponent: f64) -> Option<f64> {
+        if self.is_completed() {
+            return None;
+        }
+
+        self.samples.push(rssi);
+        self.captured_samples = self.samples.len();
+
+        if self.captured_samples < self.target_samples {
+            return None;
+        }
+
+        let rssi_at_1m =
+            calculate_reference_rssi(median(&self.samples), self.distance, path_loss_exponent);
+        self.rssi_at_1m = Some(rssi_at_1m);
+
+        Some(rssi_at_1m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH_LOSS_EXPONENT: f64 = 2.0;
+
+    #[test]
+    fn waits_for_the_target_sample_count() {
+        let mut session = CalibrationSession::new(1, 0xabcd, 1.0, 3);
+
+        assert_eq!(session.add_sample(-60.0, PATH_LOSS_EXPONENT), None);
+        assert_eq!(session.add_sample(-62.0, PATH_LOSS_EXPONENT), None);
+        assert_eq!(session.captured_samples, 2);
+        assert!(!session.is_completed());
+
+        assert_eq!(session.add_sample(-61.0, PATH_LOSS_EXPONENT), Some(-61.0));
+        assert_eq!(session.captured_samples, 3);
+        assert!(session.is_completed());
+    }
+
+    #[test]
+    fn ignores_samples_after_completion() {
+        let mut session = CalibrationSession::new(1, 0xabcd, 1.0, 1);
+
+        assert_eq!(session.add_sample(-55.0, PATH_LOSS_EXPONENT), Some(-55.0));
+        assert_eq!(session.add_sample(-90.0, PATH_LOSS_EXPONENT), None);
+        assert_eq!(session.captured_samples, 1);
+        assert_eq!(session.rssi_at_1m, Some(-55.0));
+    }
+
+    #[test]
+    fn uses_the_median_of_the_samples() {
+        let mut session = CalibrationSession::new(1, 0xabcd, 1.0, 5);
+
+        for rssi in [-60.0, -95.0, -59.0, -61.0, -20.0] {
+            session.add_sample(rssi, PATH_LOSS_EXPONENT);
+        }
+
+        assert_eq!(session.rssi_at_1m, Some(-60.0));
+    }
+
+    #[test]
+    fn refers_the_samples_to_one_meter() {
+        let mut session = CalibrationSession::new(1, 0xabcd, 10.0, 1);
+
+        // 10m are 20 dB of path loss with an exponent of 2.
+        let rssi_at_1m = session.add_sample(-80.0, PATH_LOSS_EXPONENT).unwrap();
+
+        assert!((rssi_at_1m + 60.0).abs() < 1e-9, "rssi at 1m: {rssi_at_1m}");
+    }
+}
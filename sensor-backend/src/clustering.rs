@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+use sensor_lib::Measurement;
+use sensor_lib::probe::ProbeSignature;
+use serde::Serialize;
+
+/// Set in the first octet of MAC addresses that are not assigned by the manufacturer, which
+/// includes the randomized addresses of modern devices.
+const LOCALLY_ADMINISTERED_BIT: u8 = 0x02;
+
+/// Time without a probe request after which a pseudo-device no longer takes over new addresses.
+const MAX_ROTATION_GAP_MS: u64 = 60_000;
+/// Time without a probe request after which a pseudo-device is forgotten.
+const MAX_DEVICE_AGE_MS: u64 = 60 * 60 * 1000;
+/// Interval in which forgotten pseudo-devices are removed, instead of scanning all of them for
+/// every probe request.
+const PRUNE_INTERVAL_MS: u64 = 60_000;
+
+/// 802.11 sequence numbers are 12 bits wide.
+const SEQUENCE_NUMBER_MASK: u16 = 0x0fff;
+/// Largest increment of the sequence number that still counts as continuation, devices send
+/// other frames between two probe requests.
+const MAX_SEQUENCE_GAP: u16 = 64;
+
+const RSSI_TOLERANCE: f64 = 10.0;
+const RSSI_SMOOTHING: f64 = 0.3;
+
+const MIN_CLUSTER_SCORE: f64 = 1.5;
+
+pub fn is_randomized(fingerprint: u64) -> bool {
+    (fingerprint >> 56) as u8 & LOCALLY_ADMINISTERED_BIT != 0
+}
+
+/// A device that may have used several MAC addresses.
+#[derive(Clone, Serialize)]
+pub struct PseudoDevice {
+    /// The fingerprint of the first address of the device.
+    pub id: u64,
+    pub randomized: bool,
+    pub members: Vec<u64>,
+    pub signature: Option<ProbeSignature>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    #[serde(skip)]
+    sequence_number: Option<u16>,
+    /// Smoothed RSSI of the latest probe requests per sensor.
+    #[serde(skip)]
+    rssi: HashMap<u8, f64>,
+}
+
+impl PseudoDevice {
+    fn new(measurement: &Measurement, timestamp: u64) -> Self {
+        Self {
+            id: measurement.fingerprint,
+            randomized: is_randomized(measurement.fingerprint),
+            members: vec![measurement.fingerprint],
+            signature: measurement.signature,
+            first_seen: timestamp,
+            last_seen: timestamp,
+            sequence_number: measurement.sequence_number,
+            rssi: HashMap::new(),
+        }
+    }
+
+    fn update(&mut self, sensor_id: u8, measurement: &Measurement, timestamp: u64) {
+        if !self.members.contains(&measurement.fingerprint) {
+            self.members.push(measurement.fingerprint);
+        }
+
+        self.signature = self.signature.or(measurement.signature);
+        self.last_seen = self.last_seen.max(timestamp);
+        self.sequence_number = measurement.sequence_number.or(self.sequence_number);
+
+        let rssi = measurement.rssi as f64;
+        self.rssi
+            .entry(sensor_id)
+            .and_modify(|smoothed| *smoothed += RSSI_SMOOTHING * (rssi - *smoothed))
+            .or_insert(rssi);
+    }
+
+    /// Likelihood that a new randomized address belongs to this device, from the time since the
+    /// device was last seen, the continuity of the sequence number, the similarity of the RSSI
+    /// at the same sensor and the signature of the probe request.
+    fn score(&self, sensor_id: u8, measurement: &Measurement, timestamp: u64) -> Option<f64> {
+        let gap = timestamp.saturating_sub(self.last_seen);
+        if !self.randomized || gap > MAX_ROTATION_GAP_MS {
+            return None;
+        }
+
+        let mut score = 1.0 - gap as f64 / MAX_ROTATION_GAP_MS as f64;
+        // Timing and RSSI are similar for any two devices close to each other, at least the
+        // signature or the sequence number has to match.
+        let mut identified = false;
+
+        match (self.signature, measurement.signature) {
+            (Some(a), Some(b)) if a != b => return None,
+            (Some(_), Some(_)) => {
+                score += 1.0;
+                identified = true;
+            }
+            _ => {}
+        }
+
+        if let (Some(last), Some(current)) = (self.sequence_number, measurement.sequence_number) {
+            let increment = current.wrapping_sub(last) & SEQUENCE_NUMBER_MASK;
+
+            if (1..=MAX_SEQUENCE_GAP).contains(&increment) {
+                score += 1.0 - (increment - 1) as f64 / MAX_SEQUENCE_GAP as f64;
+                identified = true;
+            }
+        }
+
+        if let Some(rssi) = self.rssi.get(&sensor_id) {
+            let difference = (rssi - measurement.rssi as f64).abs();
+            score += 1.0 - (difference / RSSI_TOLERANCE).min(2.0);
+        }
+
+        identified.then_some(score)
+    }
+}
+
+/// Groups the randomized addresses a device rotates through into a single pseudo-device.
+/// Addresses assigned by the manufacturer are a pseudo-device on their own.
+#[derive(Default)]
+pub struct DeviceClusters {
+    devices: HashMap<u64, PseudoDevice>,
+    /// Pseudo-device ID of every known address.
+    members: HashMap<u64, u64>,
+    next_prune: u64,
+}
+
+impl DeviceClusters {
+    pub fn observe(
+        &mut self,
+        sensor_id: u8,
+        measurement: &Measurement,
+        timestamp: u64,
+    ) -> &PseudoDevice {
+        if timestamp >= self.next_prune {
+            self.prune(timestamp);
+        }
+
+        let id = match self.members.get(&measurement.fingerprint) {
+            Some(id) => *id,
+            None => {
+                let id = match is_randomized(measurement.fingerprint) {
+                    true => self
+                        .best_match(sensor_id, measurement, timestamp)
+                        .unwrap_or(measurement.fingerprint),
+                    false => measurement.fingerprint,
+                };
+
+                self.members.insert(measurement.fingerprint, id);
+
+                id
+            }
+        };
+
+        let device = self
+            .devices
+            .entry(id)
+            .or_insert_with(|| PseudoDevice::new(measurement, timestamp));
+        device.update(sensor_id, measurement, timestamp);
+
+        device
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &PseudoDevice> {
+        self.devices.values()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&PseudoDevice> {
+        self.devices.get(&id)
+    }
+
+    /// The pseudo-device ID of a known address.
+    pub fn device_of(&self, fingerprint: u64) -> Option<u64> {
+        self.members.get(&fingerprint).copied()
+    }
+
+    fn prune(&mut self, timestamp: u64) {
+        let oldest = timestamp.saturating_sub(MAX_DEVICE_AGE_MS);
+        self.devices.retain(|_, device| device.last_seen >= oldest);
+        self.members.retain(|_, id| self.devices.contains_key(id));
+
+        self.next_prune = timestamp + PRUNE_INTERVAL_MS;
+    }
+
+    fn best_match(&self, sensor_id: u8, measurement: &Measurement, timestamp: u64) -> Option<u64> {
+        self.devices
+            .values()
+            .filter_map(|device| {
+                device
+                    .score(sensor_id, measurement, timestamp)
+                    .map(|score| (device, score))
+            })
+            .filter(|(_, score)| *score >= MIN_CLUSTER_SCORE)
+            // Ties go to the older pseudo-device, independent of the order of the map.
+            .max_by(|(a, a_score), (b, b_score)| {
+                a_score
+                    .total_cmp(b_score)
+                    .then(b.first_seen.cmp(&a.first_seen))
+                    .then(b.id.cmp(&a.id))
+            })
+            .map(|(device, _)| device.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RANDOMIZED: u64 = 0x0200_0000_0000_0000;
+    const SIGNATURE: ProbeSignature = ProbeSignature(0x5ea1);
+
+    fn measurement(
+        fingerprint: u64,
+        signature: Option<ProbeSignature>,
+        sequence_number: Option<u16>,
+    ) -> Measurement {
+        Measurement {
+            sensor_id: 1,
+            fingerprint,
+            signature,
+            sequence_number,
+            rssi: -60,
+            timestamp_ms: 0,
+        }
+    }
+
+    fn observe(clusters: &mut DeviceClusters, measurement: Measurement, timestamp: u64) -> u64 {
+        clusters.observe(1, &measurement, timestamp).id
+    }
+
+    #[test]
+    fn continues_the_sequence_number() {
+        let mut clusters = DeviceClusters::default();
+        let first = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 1, None, Some(4090)),
+            0,
+        );
+
+        // Wraps around from 4090 to 2.
+        let rotated = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 2, None, Some(2)),
+            1000,
+        );
+        assert_eq!(rotated, first);
+
+        let jumped = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 3, None, Some(500)),
+            2000,
+        );
+        assert_eq!(jumped, RANDOMIZED | 3);
+    }
+
+    #[test]
+    fn requires_a_matching_signature() {
+        let mut clusters = DeviceClusters::default();
+        let first = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 1, Some(SIGNATURE), None),
+            0,
+        );
+
+        let rotated = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 2, Some(SIGNATURE), None),
+            1000,
+        );
+        assert_eq!(rotated, first);
+
+        // A different signature rules the device out, even with a continuing sequence number.
+        let other = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 3, Some(ProbeSignature(0xbad)), Some(1)),
+            2000,
+        );
+        assert_eq!(other, RANDOMIZED | 3);
+    }
+
+    #[test]
+    fn keeps_addresses_of_the_manufacturer_apart() {
+        let mut clusters = DeviceClusters::default();
+        observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 1, Some(SIGNATURE), None),
+            0,
+        );
+
+        let global = observe(
+            &mut clusters,
+            measurement(0x1234, Some(SIGNATURE), None),
+            1000,
+        );
+        assert_eq!(global, 0x1234);
+
+        let timed_out = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 2, Some(SIGNATURE), None),
+            MAX_ROTATION_GAP_MS + 1,
+        );
+        assert_eq!(timed_out, RANDOMIZED | 2);
+    }
+
+    #[test]
+    fn breaks_ties_by_the_first_sighting() {
+        let mut clusters = DeviceClusters::default();
+
+        // The older pseudo-device has the higher ID, so that the order of the IDs does not decide.
+        let older = RANDOMIZED | 2;
+        let newer = RANDOMIZED | 1;
+
+        observe(&mut clusters, measurement(older, Some(SIGNATURE), None), 0);
+        // Without a signature or sequence number the address cannot join the older device.
+        observe(&mut clusters, measurement(newer, None, None), 1000);
+        observe(
+            &mut clusters,
+            measurement(newer, Some(SIGNATURE), None),
+            1000,
+        );
+        observe(
+            &mut clusters,
+            measurement(older, Some(SIGNATURE), None),
+            1000,
+        );
+
+        let rotated = observe(
+            &mut clusters,
+            measurement(RANDOMIZED | 3, Some(SIGNATURE), None),
+            2000,
+        );
+        assert_eq!(rotated, older);
+    }
+
+    #[test]
+    fn forgets_devices_after_the_prune_interval() {
+        let mut clusters = DeviceClusters::default();
+        observe(&mut clusters, measurement(0x1234, None, None), 0);
+
+        observe(
+            &mut clusters,
+            measurement(0x5678, None, None),
+            MAX_DEVICE_AGE_MS,
+        );
+        assert!(clusters.get(0x1234).is_some());
+
+        observe(
+            &mut clusters,
+            measurement(0x5678, None, None),
+            MAX_DEVICE_AGE_MS + PRUNE_INTERVAL_MS,
+        );
+        assert!(clusters.get(0x1234).is_none());
+        assert_eq!(clusters.device_of(0x1234), None);
+    }
+}
@@ -0,0 +1,562 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::auth::{AuthConfig, SensorKeyConfig};
+use crate::rssi::RssiFilter;
+use crate::storage::StorageConfig;
+use crate::tracking::TrackerConfig;
+
+/// Slowest replay speed, slower speeds would stretch the offsets beyond what a `Duration` holds.
+const MIN_REPLAY_SPEED: f64 = 0.001;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path of the TOML configuration file.
+    #[arg(long, env = "CONFIG")]
+    config: Option<PathBuf>,
+
+    #[arg(long, env = "UDP_BIND")]
+    udp_bind: Option<SocketAddr>,
+    #[arg(long, env = "HTTP_BIND")]
+    http_bind: Option<SocketAddr>,
+    #[arg(long, env = "PACKET_POSITIONS")]
+    packet_positions: Option<PacketPositionPolicy>,
+    /// Deprecated and ignored, the WebSocket streams push every change.
+    #[arg(long, env = "WEBSOCKET_INTERVAL", hide = true)]
+    websocket_interval: Option<f64>,
+    /// File to which every received datagram is appended.
+    #[arg(long, env = "RECORD_PATH")]
+    record_path: Option<PathBuf>,
+
+    /// Replays a recording instead of listening for datagrams.
+    #[arg(long, env = "REPLAY")]
+    replay: Option<PathBuf>,
+    /// Playback speed of the replay relative to the recording, at least 0.001, 0 replays as fast
+    /// as possible.
+    #[arg(long, env = "REPLAY_SPEED", default_value_t = 1.0)]
+    replay_speed: f64,
+
+    #[arg(long, env = "RSSI_FILTER")]
+    rssi_filter: Option<RssiFilterKind>,
+    #[arg(long, env = "MIN_MEASUREMENT_ENTRIES")]
+    min_measurement_entries: Option<usize>,
+    /// Seconds after which measurements are discarded.
+    #[arg(long, env = "MAX_MEASUREMENT_AGE")]
+    max_measurement_age: Option<f64>,
+    #[arg(long, env = "CALIBRATED_RSSI_AT_1M", allow_hyphen_values = true)]
+    calibrated_rssi_at_1m: Option<f64>,
+    #[arg(long, env = "KALMAN_PROCESS_NOISE")]
+    kalman_process_noise: Option<f64>,
+    #[arg(long, env = "KALMAN_MEASUREMENT_NOISE")]
+    kalman_measurement_noise: Option<f64>,
+
+    #[arg(long, env = "TRACKER_ACCELERATION_NOISE")]
+    tracker_acceleration_noise: Option<f64>,
+    /// Seconds without a fix after which a track is restarted.
+    #[arg(long, env = "TRACKER_MAX_GAP")]
+    tracker_max_gap: Option<f64>,
+    /// Seconds without a fix after which the position of a device is removed.
+    #[arg(long, env = "DEVICE_EXPIRY")]
+    device_expiry: Option<f64>,
+
+    #[arg(long, env = "DATABASE_PATH")]
+    database_path: Option<PathBuf>,
+    #[arg(long, env = "STORE_MEASUREMENTS")]
+    store_measurements: Option<bool>,
+    /// Seconds after which stored measurements are deleted.
+    #[arg(long, env = "MEASUREMENT_RETENTION")]
+    measurement_retention: Option<f64>,
+    /// Seconds after which stored positions are deleted.
+    #[arg(long, env = "POSITION_RETENTION")]
+    position_retention: Option<f64>,
+
+    /// Key of a sensor as `<sensor ID>:<hex encoded key>`, replaces a key of the same sensor in
+    /// the configuration file.
+    #[arg(long = "sensor-key", env = "SENSOR_KEYS", value_delimiter = ',')]
+    sensor_keys: Vec<SensorKeyConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub sensors: SensorsConfig,
+    pub rssi: RssiConfig,
+    pub tracker: TrackerConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    /// Only given on the command line.
+    #[serde(skip)]
+    pub replay: Option<ReplayConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    #[schema(value_type = String)]
+    pub udp_bind: SocketAddr,
+    #[schema(value_type = String)]
+    pub http_bind: SocketAddr,
+    #[schema(value_type = Option<String>)]
+    pub record_path: Option<PathBuf>,
+    /// Deprecated and ignored since the WebSocket streams push every change, still accepted so
+    /// that existing configurations keep working.
+    #[serde(skip_serializing)]
+    #[schema(ignore)]
+    pub websocket_interval: Option<f64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            udp_bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            http_bind: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            record_path: None,
+            websocket_interval: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorsConfig {
+    pub packet_positions: PacketPositionPolicy,
+}
+
+/// How the positions reported by the sensors themselves are treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ValueEnum, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketPositionPolicy {
+    /// Sensors are created and moved by their reports.
+    #[default]
+    Accept,
+    /// Only the registry defines the sensors and their positions.
+    Ignore,
+    /// Like `ignore`, but reports that differ from the registry are recorded as conflicts.
+    Flag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RssiFilterKind {
+    Median,
+    Kalman,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RssiConfig {
+    pub filter: RssiFilterKind,
+    pub min_measurement_entries: usize,
+    #[serde(with = "seconds")]
+    #[schema(
+        value_type = f64,
+        default = json!(RssiConfig::default().max_measurement_age.as_secs_f64())
+    )]
+    pub max_measurement_age: Duration,
+    pub calibrated_rssi_at_1m: f64,
+    pub kalman_process_noise: f64,
+    pub kalman_measurement_noise: f64,
+}
+
+impl Default for RssiConfig {
+    fn default() -> Self {
+        Self {
+            filter: RssiFilterKind::Median,
+            min_measurement_entries: 10,
+            max_measurement_age: Duration::from_secs(60),
+            calibrated_rssi_at_1m: -70.0,
+            kalman_process_noise: 0.5,
+            kalman_measurement_noise: 16.0,
+        }
+    }
+}
+
+impl RssiConfig {
+    pub fn filter(&self) -> RssiFilter {
+        match self.filter {
+            RssiFilterKind::Median => RssiFilter::Median,
+            RssiFilterKind::Kalman => RssiFilter::Kalman {
+                process_noise: self.kalman_process_noise,
+                measurement_noise: self.kalman_measurement_noise,
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file given on the command line, if any, and applies the
+    /// overrides of the environment and command line on top of it.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::from_cli(Cli::parse())
+    }
+
+    fn from_cli(cli: Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        config.apply(cli)?;
+        config.validate()?;
+
+        if config.server.websocket_interval.is_some() {
+            warn!(
+                "server.websocket_interval (WEBSOCKET_INTERVAL, --websocket-interval) is \
+                 deprecated and ignored, the WebSocket streams push every change"
+            );
+        }
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read config {}: {}", path.display(), e))?;
+
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse config {}: {}", path.display(), e))
+    }
+
+    fn apply(&mut self, cli: Cli) -> anyhow::Result<()> {
+        fn set<T>(target: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+
+        let secs = |value: Option<f64>| value.map(Duration::try_from_secs_f64).transpose();
+
+        set(&mut self.server.udp_bind, cli.udp_bind);
+        set(&mut self.server.http_bind, cli.http_bind);
+        if cli.websocket_interval.is_some() {
+            self.server.websocket_interval = cli.websocket_interval;
+        }
+        set(&mut self.sensors.packet_positions, cli.packet_positions);
+        if cli.record_path.is_some() {
+            self.server.record_path = cli.record_path;
+        }
+
+        self.replay = cli.replay.map(|path| ReplayConfig {
+            path,
+            speed: cli.replay_speed,
+        });
+
+        set(&mut self.rssi.filter, cli.rssi_filter);
+        set(
+            &mut self.rssi.min_measurement_entries,
+            cli.min_measurement_entries,
+        );
+        set(
+            &mut self.rssi.max_measurement_age,
+            secs(cli.max_measurement_age)?,
+        );
+        set(
+            &mut self.rssi.calibrated_rssi_at_1m,
+            cli.calibrated_rssi_at_1m,
+        );
+        set(
+            &mut self.rssi.kalman_process_noise,
+            cli.kalman_process_noise,
+        );
+        set(
+            &mut self.rssi.kalman_measurement_noise,
+            cli.kalman_measurement_noise,
+        );
+
+        set(
+            &mut self.tracker.acceleration_noise,
+            cli.tracker_acceleration_noise,
+        );
+        set(&mut self.tracker.max_gap, secs(cli.tracker_max_gap)?);
+        set(&mut self.tracker.device_expiry, secs(cli.device_expiry)?);
+
+        if cli.database_path.is_some() {
+            self.storage.database_path = cli.database_path;
+        }
+        set(&mut self.storage.store_measurements, cli.store_measurements);
+        set(
+            &mut self.storage.measurement_retention,
+            secs(cli.measurement_retention)?,
+        );
+        set(
+            &mut self.storage.position_retention,
+            secs(cli.position_retention)?,
+        );
+
+        for key in cli.sensor_keys {
+            self.auth.keys.retain(|k| k.sensor_id != key.sensor_id);
+            self.auth.keys.push(key);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let positive = |name: &str, value: f64| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("{} must be positive, got {}", name, value))
+            }
+        };
+
+        if let Some(replay) = &self.replay
+            && !(replay.speed == 0.0
+                || (replay.speed.is_finite() && replay.speed >= MIN_REPLAY_SPEED))
+        {
+            return Err(anyhow::anyhow!(
+                "replay speed must be 0 or at least {}, got {}",
+                MIN_REPLAY_SPEED,
+                replay.speed
+            ));
+        }
+
+        if self.rssi.min_measurement_entries == 0 {
+            return Err(anyhow::anyhow!(
+                "rssi.min_measurement_entries must be at least 1"
+            ));
+        }
+        positive(
+            "rssi.max_measurement_age",
+            self.rssi.max_measurement_age.as_secs_f64(),
+        )?;
+        if !self.rssi.calibrated_rssi_at_1m.is_finite() {
+            return Err(anyhow::anyhow!("rssi.calibrated_rssi_at_1m must be finite"));
+        }
+        positive("rssi.kalman_process_noise", self.rssi.kalman_process_noise)?;
+        positive(
+            "rssi.kalman_measurement_noise",
+            self.rssi.kalman_measurement_noise,
+        )?;
+
+        positive(
+            "tracker.acceleration_noise",
+            self.tracker.acceleration_noise,
+        )?;
+        positive("tracker.max_gap", self.tracker.max_gap.as_secs_f64())?;
+        positive(
+            "tracker.device_expiry",
+            self.tracker.device_expiry.as_secs_f64(),
+        )?;
+
+        positive(
+            "storage.measurement_retention",
+            self.storage.measurement_retention.as_secs_f64(),
+        )?;
+        positive(
+            "storage.position_retention",
+            self.storage.position_retention.as_secs_f64(),
+        )?;
+
+        for (i, key) in self.auth.keys.iter().enumerate() {
+            if self.auth.keys[..i]
+                .iter()
+                .any(|k| k.sensor_id == key.sensor_id)
+            {
+                return Err(anyhow::anyhow!(
+                    "auth.keys contains sensor {} more than once",
+                    key.sensor_id
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// (De)serializes a [`Duration`] as fractional seconds.
+pub mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+
+        Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/config.toml");
+
+    fn load(args: &[&str]) -> anyhow::Result<Config> {
+        let cli = Cli::try_parse_from(["sensor-backend"].iter().chain(args))?;
+
+        Config::from_cli(cli)
+    }
+
+    fn error(args: &[&str]) -> String {
+        load(args).unwrap_err().to_string()
+    }
+
+    fn key(sensor_id: u8, byte: &str) -> String {
+        format!("{}:{}", sensor_id, byte.repeat(32))
+    }
+
+    #[test]
+    fn defaults_without_a_file() {
+        let config = load(&[]).unwrap();
+
+        assert_eq!(config.server.http_bind, ServerConfig::default().http_bind);
+        assert_eq!(config.rssi.filter, RssiFilterKind::Median);
+        assert!(config.storage.database_path.is_none());
+        assert!(config.replay.is_none());
+    }
+
+    #[test]
+    fn loads_the_file() {
+        let config = load(&["--config", FIXTURE]).unwrap();
+
+        assert_eq!(config.server.udp_bind, "127.0.0.1:4000".parse().unwrap());
+        assert_eq!(config.sensors.packet_positions, PacketPositionPolicy::Flag);
+        assert_eq!(config.rssi.filter, RssiFilterKind::Kalman);
+        assert_eq!(config.rssi.min_measurement_entries, 5);
+        assert_eq!(
+            config.rssi.max_measurement_age,
+            Duration::from_secs_f64(30.5)
+        );
+        assert_eq!(config.rssi.kalman_measurement_noise, 9.0);
+        // Keys missing from the file keep their default.
+        assert_eq!(config.rssi.kalman_process_noise, 0.5);
+        assert!(config.storage.store_measurements);
+        assert_eq!(config.auth.keys.len(), 2);
+    }
+
+    #[test]
+    fn overrides_the_file_from_the_command_line() {
+        let config = load(&[
+            "--config",
+            FIXTURE,
+            "--http-bind",
+            "127.0.0.1:8081",
+            "--rssi-filter",
+            "median",
+            "--max-measurement-age",
+            "10",
+            "--calibrated-rssi-at-1m",
+            "-58",
+            "--store-measurements",
+            "false",
+        ])
+        .unwrap();
+
+        assert_eq!(config.server.http_bind, "127.0.0.1:8081".parse().unwrap());
+        assert_eq!(config.server.udp_bind, "127.0.0.1:4000".parse().unwrap());
+        assert_eq!(config.rssi.filter, RssiFilterKind::Median);
+        assert_eq!(config.rssi.max_measurement_age, Duration::from_secs(10));
+        assert_eq!(config.rssi.calibrated_rssi_at_1m, -58.0);
+        assert!(!config.storage.store_measurements);
+    }
+
+    // The only test that touches the environment, no other test reads the device expiry.
+    #[test]
+    fn overrides_the_file_from_the_environment_and_the_environment_from_the_command_line() {
+        // SAFETY: The variable is only read by clap in these tests, which reads it through the
+        // synchronised environment of the standard library.
+        unsafe { std::env::set_var("DEVICE_EXPIRY", "120") };
+
+        let from_env = load(&["--config", FIXTURE]).unwrap();
+        let from_cli = load(&["--config", FIXTURE, "--device-expiry", "30"]).unwrap();
+
+        unsafe { std::env::remove_var("DEVICE_EXPIRY") };
+
+        assert_eq!(from_env.tracker.device_expiry, Duration::from_secs(120));
+        assert_eq!(from_cli.tracker.device_expiry, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn replaces_keys_of_the_same_sensor() {
+        let config = load(&[
+            "--config",
+            FIXTURE,
+            "--sensor-key",
+            &key(2, "ff"),
+            "--sensor-key",
+            &key(3, "01"),
+        ])
+        .unwrap();
+
+        let keys: Vec<(u8, u8)> = config
+            .auth
+            .keys
+            .iter()
+            .map(|k| (k.sensor_id, k.key[0]))
+            .collect();
+
+        assert_eq!(keys, vec![(1, 0x00), (2, 0xff), (3, 0x01)]);
+    }
+
+    #[test]
+    fn applies_the_replay_options() {
+        let config = load(&["--replay", "capture.rec", "--replay-speed", "4"]).unwrap();
+
+        let replay = config.replay.unwrap();
+        assert_eq!(replay.path, PathBuf::from("capture.rec"));
+        assert_eq!(replay.speed, 4.0);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(error(&["--min-measurement-entries", "0"]).contains("min_measurement_entries"));
+        assert!(error(&["--max-measurement-age", "0"]).contains("max_measurement_age"));
+        assert!(error(&["--kalman-process-noise=-1"]).contains("kalman_process_noise"));
+        assert!(error(&["--kalman-measurement-noise", "NaN"]).contains("kalman_measurement_noise"));
+        assert!(error(&["--tracker-max-gap", "0"]).contains("max_gap"));
+        assert!(error(&["--position-retention", "0"]).contains("position_retention"));
+        assert!(error(&["--replay", "capture.rec", "--replay-speed", "0.0001"]).contains("speed"));
+        assert!(error(&["--max-measurement-age=-1"]).contains("negative"));
+    }
+
+    #[test]
+    fn rejects_duplicate_keys_in_the_file() {
+        let config: Config = toml::from_str(&format!(
+            "[[auth.keys]]\nsensor_id = 1\nkey = \"{0}\"\n\n\
+             [[auth.keys]]\nsensor_id = 1\nkey = \"{0}\"\n",
+            "00".repeat(32)
+        ))
+        .unwrap();
+
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .to_string()
+                .contains("more than once")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_keys_in_the_file() {
+        assert!(toml::from_str::<Config>("[rssi]\nfilter = \"mean\"").is_err());
+        assert!(toml::from_str::<Config>("[rssi]\nmin_entries = 3").is_err());
+        assert!(toml::from_str::<Config>("[tracker]\nmax_gap = -1.0").is_err());
+        assert!(toml::from_str::<Config>("[[auth.keys]]\nsensor_id = 1\nkey = \"abcd\"").is_err());
+    }
+
+    #[test]
+    fn rejects_a_missing_file() {
+        assert!(error(&["--config", "/nonexistent/config.toml"]).contains("Failed to read config"));
+    }
+}
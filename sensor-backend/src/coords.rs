@@ -1,47 +1,491 @@
-use nalgebra::{Matrix3, RowVector3, Vector3};
+use std::fmt;
+
+use nalgebra::{Matrix3, Vector2, Vector3};
+use serde::Serialize;
+use utoipa::ToSchema;
 
 use crate::sensors::Sensor;
 
-pub fn transform_local_to_global(
-    local_x: f64,
-    local_y: f64,
-    s1: &Sensor,
-    s2: &Sensor,
-    s3: &Sensor,
-) -> Option<(f64, f64)> {
-    let mean_lat = (s1.lat + s2.lat + s3.lat) / 3.0;
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+pub const MIN_GEOREFERENCE_SENSORS: usize = 2;
+
+const GEODETIC_ITERATIONS: usize = 5;
+
+/// Smallest scale in meters per local unit, below which the global positions of the sensors are
+/// considered to coincide.
+const MIN_GEOREFERENCE_SCALE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Serialize)]
+pub enum GeoreferenceError {
+    NotEnoughSensors(usize),
+    DegenerateGeometry,
+}
+
+impl fmt::Display for GeoreferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoreferenceError::NotEnoughSensors(n) => write!(
+                f,
+                "{} sensors with local and global coordinates available, at least {} required",
+                n, MIN_GEOREFERENCE_SENSORS
+            ),
+            GeoreferenceError::DegenerateGeometry => {
+                write!(f, "Sensors share the same local or global coordinates")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeoreferenceError {}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct SensorResidual {
+    pub sensor_id: u8,
+    pub residual: f64,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct GeoreferenceReport {
+    pub origin_lat: f64,
+    pub origin_lon: f64,
+    pub scale: f64,
+    pub rotation: f64,
+    pub reflected: bool,
+    pub rms_residual: f64,
+    pub residuals: Vec<SensorResidual>,
+}
+
+/// Similarity transform from the local sensor coordinates into the east-north-up tangent plane
+/// of the WGS84 ellipsoid at the geodetic centroid of the sensors.
+pub struct Georeference {
+    origin_lat: f64,
+    origin_lon: f64,
+    origin_ecef: Vector3<f64>,
+    ecef_to_enu: Matrix3<f64>,
+    a: f64,
+    b: f64,
+    translation: Vector2<f64>,
+    reflected: bool,
+    residuals: Vec<SensorResidual>,
+}
+
+impl Georeference {
+    pub fn fit<'a>(
+        sensors: impl IntoIterator<Item = &'a Sensor>,
+    ) -> Result<Self, GeoreferenceError> {
+        let mut sensors: Vec<&Sensor> = sensors
+            .into_iter()
+            .filter(|sensor| sensor.has_global_position())
+            .collect();
+        sensors.sort_by_key(|sensor| sensor.id);
+
+        if sensors.len() < MIN_GEOREFERENCE_SENSORS {
+            return Err(GeoreferenceError::NotEnoughSensors(sensors.len()));
+        }
+
+        let n = sensors.len() as f64;
+        let origin_lat = sensors.iter().map(|s| s.lat).sum::<f64>() / n;
+        let origin_lon = sensors.iter().map(|s| s.lon).sum::<f64>() / n;
+
+        let origin_ecef = geodetic_to_ecef(origin_lat, origin_lon);
+        let ecef_to_enu = ecef_to_enu_rotation(origin_lat, origin_lon);
+
+        let pairs: Vec<(Vector2<f64>, Vector2<f64>)> = sensors
+            .iter()
+            .map(|s| {
+                let enu = ecef_to_enu * (geodetic_to_ecef(s.lat, s.lon) - origin_ecef);
+
+                (Vector2::new(s.x, s.y), Vector2::new(enu.x, enu.y))
+            })
+            .collect();
+
+        let proper = fit_similarity(&pairs, false).ok_or(GeoreferenceError::DegenerateGeometry)?;
+
+        // Two sensors cannot tell whether the local frame is mirrored, more sensors can.
+        let (a, b, translation, reflected) = if sensors.len() > MIN_GEOREFERENCE_SENSORS {
+            match fit_similarity(&pairs, true) {
+                Some(mirrored)
+                    if sum_squared_error(&pairs, mirrored) < sum_squared_error(&pairs, proper) =>
+                {
+                    mirrored
+                }
+                _ => proper,
+            }
+        } else {
+            proper
+        };
+
+        let mut georeference = Self {
+            origin_lat,
+            origin_lon,
+            origin_ecef,
+            ecef_to_enu,
+            a,
+            b,
+            translation,
+            reflected,
+            residuals: Vec::new(),
+        };
+
+        georeference.residuals = sensors
+            .iter()
+            .zip(&pairs)
+            .map(|(sensor, (_, enu))| SensorResidual {
+                sensor_id: sensor.id,
+                residual: (georeference.local_to_enu(sensor.x, sensor.y) - enu).norm(),
+            })
+            .collect();
+
+        Ok(georeference)
+    }
+
+    pub fn local_to_global(&self, x: f64, y: f64) -> (f64, f64) {
+        let enu = self.local_to_enu(x, y);
+        let ecef =
+            self.origin_ecef + self.ecef_to_enu.transpose() * Vector3::new(enu.x, enu.y, 0.0);
+
+        ecef_to_geodetic(&ecef)
+    }
+
+    pub fn global_to_local(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let enu = self.ecef_to_enu * (geodetic_to_ecef(lat, lon) - self.origin_ecef);
+        let enu = Vector2::new(enu.x, enu.y) - self.translation;
+
+        // Inverse of the rotation and scale [[a, -b], [b, a]].
+        let scale_squared = self.a.powi(2) + self.b.powi(2);
+        let x = (self.a * enu.x + self.b * enu.y) / scale_squared;
+        let y = (self.a * enu.y - self.b * enu.x) / scale_squared;
+
+        if self.reflected { (x, -y) } else { (x, y) }
+    }
+
+    pub fn report(&self) -> GeoreferenceReport {
+        let rms_residual = (self
+            .residuals
+            .iter()
+            .map(|r| r.residual.powi(2))
+            .sum::<f64>()
+            / self.residuals.len() as f64)
+            .sqrt();
+
+        GeoreferenceReport {
+            origin_lat: self.origin_lat,
+            origin_lon: self.origin_lon,
+            scale: self.a.hypot(self.b),
+            rotation: self.b.atan2(self.a).to_degrees(),
+            reflected: self.reflected,
+            rms_residual,
+            residuals: self.residuals.clone(),
+        }
+    }
+
+    fn local_to_enu(&self, x: f64, y: f64) -> Vector2<f64> {
+        let y = if self.reflected { -y } else { y };
+
+        Vector2::new(self.a * x - self.b * y, self.b * x + self.a * y) + self.translation
+    }
+}
+
+type Similarity = (f64, f64, Vector2<f64>, bool);
+
+/// Closed form least squares fit of `enu = [[a, -b], [b, a]] * local + t`, with the local y axis
+/// mirrored if `reflected` is set.
+fn fit_similarity(pairs: &[(Vector2<f64>, Vector2<f64>)], reflected: bool) -> Option<Similarity> {
+    let mirror = |p: &Vector2<f64>| {
+        if reflected {
+            Vector2::new(p.x, -p.y)
+        } else {
+            *p
+        }
+    };
+
+    let n = pairs.len() as f64;
+    let local_mean = pairs.iter().map(|(l, _)| mirror(l)).sum::<Vector2<f64>>() / n;
+    let enu_mean = pairs.iter().map(|(_, e)| e).sum::<Vector2<f64>>() / n;
+
+    let (mut sxx, mut sa, mut sb) = (0.0, 0.0, 0.0);
+    for (local, enu) in pairs {
+        let l = mirror(local) - local_mean;
+        let e = enu - enu_mean;
+
+        sxx += l.norm_squared();
+        sa += l.x * e.x + l.y * e.y;
+        sb += l.x * e.y - l.y * e.x;
+    }
+
+    if sxx <= f64::EPSILON {
+        return None;
+    }
+
+    let a = sa / sxx;
+    let b = sb / sxx;
+
+    // Coincident global positions collapse every local position onto one point, which has no
+    // inverse.
+    if a.hypot(b) < MIN_GEOREFERENCE_SCALE {
+        return None;
+    }
+    let translation = enu_mean
+        - Vector2::new(
+            a * local_mean.x - b * local_mean.y,
+            b * local_mean.x + a * local_mean.y,
+        );
+
+    Some((a, b, translation, reflected))
+}
+
+fn sum_squared_error(
+    pairs: &[(Vector2<f64>, Vector2<f64>)],
+    (a, b, t, reflected): Similarity,
+) -> f64 {
+    pairs
+        .iter()
+        .map(|(local, enu)| {
+            let y = if reflected { -local.y } else { local.y };
+            let estimate = Vector2::new(a * local.x - b * y, b * local.x + a * y) + t;
+
+            (estimate - enu).norm_squared()
+        })
+        .sum()
+}
+
+fn geodetic_to_ecef(lat: f64, lon: f64) -> Vector3<f64> {
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+
+    let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat.powi(2)).sqrt();
+
+    Vector3::new(
+        n * cos_lat * cos_lon,
+        n * cos_lat * sin_lon,
+        n * (1.0 - WGS84_E2) * sin_lat,
+    )
+}
+
+fn ecef_to_geodetic(ecef: &Vector3<f64>) -> (f64, f64) {
+    let lon = ecef.y.atan2(ecef.x);
+    let p = ecef.x.hypot(ecef.y);
+
+    let mut lat = ecef.z.atan2(p * (1.0 - WGS84_E2));
+    for _ in 0..GEODETIC_ITERATIONS {
+        let sin_lat = lat.sin();
+        let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat.powi(2)).sqrt();
+        let h = p / lat.cos() - n;
+
+        lat = ecef.z.atan2(p * (1.0 - WGS84_E2 * n / (n + h)));
+    }
+
+    (lat.to_degrees(), lon.to_degrees())
+}
+
+fn ecef_to_enu_rotation(lat: f64, lon: f64) -> Matrix3<f64> {
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+
+    Matrix3::new(
+        -sin_lon,
+        cos_lon,
+        0.0,
+        -sin_lat * cos_lon,
+        -sin_lat * sin_lon,
+        cos_lat,
+        cos_lat * cos_lon,
+        cos_lat * sin_lon,
+        sin_lat,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use sensor_lib::Environment;
+
+    use super::*;
+
+    const LAT: f64 = 50.5648;
+    const LON: f64 = 9.6845;
+
+    fn sensor(id: u8, x: f64, y: f64, lat: f64, lon: f64) -> Sensor {
+        Sensor {
+            id,
+            x,
+            y,
+            lat,
+            lon,
+            environment: Environment::FreeSpace,
+            rssi_at_1m: -70.0,
+            rssi_offset: 0.0,
+            path_loss: None,
+            label: None,
+            enabled: true,
+            conflict: None,
+        }
+    }
+
+    /// Geodetic position of a point east and north of `LAT`, `LON` in meters, using the
+    /// meridian and prime vertical radii of curvature of the WGS84 ellipsoid.
+    fn offset(east: f64, north: f64) -> (f64, f64) {
+        let sin_lat = LAT.to_radians().sin();
+        let w = 1.0 - WGS84_E2 * sin_lat.powi(2);
+        let meridian = WGS84_A * (1.0 - WGS84_E2) / w.powf(1.5);
+        let prime_vertical = WGS84_A / w.sqrt();
+
+        (
+            LAT + (north / meridian).to_degrees(),
+            LON + (east / (prime_vertical * LAT.to_radians().cos())).to_degrees(),
+        )
+    }
+
+    /// Sensors of a local frame that is rotated counterclockwise by `rotation` degrees
+    /// against east and north, with its y axis mirrored if `reflected` is set.
+    fn sensors(rotation: f64, reflected: bool) -> Vec<Sensor> {
+        let (sin, cos) = rotation.to_radians().sin_cos();
+
+        [(0.0, 0.0), (12.0, 0.0), (12.0, 9.0), (0.0, 9.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| {
+                let y_enu = if reflected { -y } else { y };
+                let (lat, lon) = offset(x * cos - y_enu * sin, x * sin + y_enu * cos);
+
+                sensor(i as u8 + 1, x, y, lat, lon)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converts_known_points_to_ecef() {
+        let equator = geodetic_to_ecef(0.0, 0.0);
+        assert!((equator - Vector3::new(WGS84_A, 0.0, 0.0)).norm() < 1e-6);
+
+        // Semi-minor axis of WGS84.
+        let pole = geodetic_to_ecef(90.0, 0.0);
+        assert!(pole.x.abs() < 1e-6 && pole.y.abs() < 1e-6);
+        assert!((pole.z - 6_356_752.314_245).abs() < 1e-3, "z: {}", pole.z);
+
+        let east = geodetic_to_ecef(0.0, 90.0);
+        assert!((east - Vector3::new(0.0, WGS84_A, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn round_trips_geodetic_coordinates() {
+        for (lat, lon) in [
+            (LAT, LON),
+            (-33.8568, 151.2153),
+            (0.0, 0.0),
+            (78.2232, -15.6267),
+        ] {
+            let (lat2, lon2) = ecef_to_geodetic(&geodetic_to_ecef(lat, lon));
+
+            assert!((lat - lat2).abs() < 1e-9, "lat: {} {}", lat, lat2);
+            assert!((lon - lon2).abs() < 1e-9, "lon: {} {}", lon, lon2);
+        }
+    }
+
+    #[test]
+    fn round_trips_local_coordinates() {
+        let sensors = sensors(25.0, false);
+        let georeference = Georeference::fit(&sensors).unwrap();
+
+        for (x, y) in [(0.0, 0.0), (6.0, 4.5), (-20.0, 35.0), (150.0, -80.0)] {
+            let (lat, lon) = georeference.local_to_global(x, y);
+            let (x2, y2) = georeference.global_to_local(lat, lon);
+
+            assert!(
+                (x - x2).abs() < 1e-6 && (y - y2).abs() < 1e-6,
+                "{x} {y}: {x2} {y2}"
+            );
+        }
+    }
+
+    #[test]
+    fn fits_sensors_at_known_positions() {
+        let sensors = sensors(30.0, false);
+        let report = Georeference::fit(&sensors).unwrap().report();
+
+        assert!((report.scale - 1.0).abs() < 1e-4, "scale: {}", report.scale);
+        assert!(
+            (report.rotation - 30.0).abs() < 1e-3,
+            "rotation: {}",
+            report.rotation
+        );
+        assert!(!report.reflected);
+        assert!(
+            report.rms_residual < 1e-3,
+            "residual: {}",
+            report.rms_residual
+        );
+        assert_eq!(report.residuals.len(), sensors.len());
+    }
 
-    let meters_per_deg_lat = 111_320.0;
-    let meters_per_deg_lon = 111_320.0 * mean_lat.to_radians().cos();
+    #[test]
+    fn maps_local_positions_to_known_points() {
+        let georeference = Georeference::fit(&sensors(0.0, false)).unwrap();
 
-    let global_x1 = (s1.lon - s1.lon) * meters_per_deg_lon;
-    let global_y1 = (s1.lat - s1.lat) * meters_per_deg_lat;
+        let (lat, lon) = georeference.local_to_global(5.0, 7.0);
+        let (expected_lat, expected_lon) = offset(5.0, 7.0);
 
-    let global_x2 = (s2.lon - s1.lon) * meters_per_deg_lon;
-    let global_y2 = (s2.lat - s1.lat) * meters_per_deg_lat;
+        // About 1 mm.
+        assert!(
+            (lat - expected_lat).abs() < 1e-8,
+            "lat: {} {}",
+            lat,
+            expected_lat
+        );
+        assert!(
+            (lon - expected_lon).abs() < 1e-8,
+            "lon: {} {}",
+            lon,
+            expected_lon
+        );
+    }
 
-    let global_x3 = (s3.lon - s1.lon) * meters_per_deg_lon;
-    let global_y3 = (s3.lat - s1.lat) * meters_per_deg_lat;
+    #[test]
+    fn detects_mirrored_frames() {
+        let report = Georeference::fit(&sensors(-60.0, true)).unwrap().report();
 
-    let mat_local = Matrix3::from_rows(&[
-        RowVector3::new(s1.x, s1.y, 1.0),
-        RowVector3::new(s2.x, s2.y, 1.0),
-        RowVector3::new(s3.x, s3.y, 1.0),
-    ]);
+        assert!(report.reflected);
+        assert!(
+            report.rms_residual < 1e-3,
+            "residual: {}",
+            report.rms_residual
+        );
+    }
 
-    let vec_global_x = Vector3::new(global_x1, global_x2, global_x3);
-    let vec_global_y = Vector3::new(global_y1, global_y2, global_y3);
+    #[test]
+    fn requires_two_sensors_with_global_positions() {
+        let sensors = [sensor(1, 0.0, 0.0, LAT, LON), sensor(2, 5.0, 0.0, 0.0, 0.0)];
 
-    let inv_local = mat_local.try_inverse()?;
+        assert!(matches!(
+            Georeference::fit(&sensors),
+            Err(GeoreferenceError::NotEnoughSensors(1))
+        ));
+    }
 
-    let coeff_x = inv_local * vec_global_x;
-    let coeff_y = inv_local * vec_global_y;
+    #[test]
+    fn rejects_sensors_at_the_same_local_position() {
+        let (lat, lon) = offset(5.0, 0.0);
+        let sensors = [sensor(1, 1.0, 1.0, LAT, LON), sensor(2, 1.0, 1.0, lat, lon)];
 
-    let gx = coeff_x[0] * local_x + coeff_x[1] * local_y + coeff_x[2];
-    let gy = coeff_y[0] * local_x + coeff_y[1] * local_y + coeff_y[2];
+        assert!(matches!(
+            Georeference::fit(&sensors),
+            Err(GeoreferenceError::DegenerateGeometry)
+        ));
+    }
 
-    let lon = s1.lon + gx / meters_per_deg_lon;
-    let lat = s1.lat + gy / meters_per_deg_lat;
+    #[test]
+    fn rejects_sensors_at_the_same_global_position() {
+        let sensors = [
+            sensor(1, 0.0, 0.0, LAT, LON),
+            sensor(2, 5.0, 0.0, LAT, LON),
+            sensor(3, 0.0, 5.0, LAT, LON),
+        ];
 
-    Some((lat, lon))
+        assert!(matches!(
+            Georeference::fit(&sensors),
+            Err(GeoreferenceError::DegenerateGeometry)
+        ));
+    }
 }
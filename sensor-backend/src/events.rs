@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use crate::sensors::{Sensor, Trilateration, unix_millis};
+
+/// Events buffered per subscriber before it lags behind and misses events.
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+/// Latest events kept to resume a stream after a reconnect.
+const EVENT_HISTORY_LENGTH: usize = 1024;
+
+/// A change of the state of the sensor service, published to all subscribers.
+#[derive(Clone, Serialize, ToSchema)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// A new or updated position of a device.
+    PositionUpdated(Trilateration),
+    /// A sensor was added or its position or calibration changed.
+    SensorUpdated(Sensor),
+    /// The sensor was removed from the registry.
+    SensorRemoved { id: u8 },
+    /// The device was not located within the expiry time and its position was removed.
+    DeviceExpired { fingerprint: u64 },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::PositionUpdated(_) => "position_updated",
+            Event::SensorUpdated(_) => "sensor_updated",
+            Event::SensorRemoved { .. } => "sensor_removed",
+            Event::DeviceExpired { .. } => "device_expired",
+        }
+    }
+}
+
+/// An event numbered in the order it was published, starting at 1.
+#[derive(Clone)]
+pub struct SequencedEvent {
+    pub sequence: u64,
+    pub event: Event,
+}
+
+pub struct Subscription {
+    pub receiver: broadcast::Receiver<SequencedEvent>,
+    /// The buffered events after the requested one, `None` if some of them are gone and the
+    /// subscriber needs a snapshot instead.
+    pub missed: Option<Vec<SequencedEvent>>,
+    /// Sequence of the last event published before the subscription.
+    pub last_sequence: u64,
+}
+
+struct History {
+    next_sequence: u64,
+    events: VecDeque<SequencedEvent>,
+}
+
+/// Publishes events to all subscribers and keeps the latest ones for resuming subscribers.
+pub struct EventBus {
+    /// Distinguishes the event IDs of different runs of the backend.
+    run: u64,
+    sender: broadcast::Sender<SequencedEvent>,
+    history: Mutex<History>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            run: unix_millis(),
+            sender: broadcast::Sender::new(EVENT_CHANNEL_CAPACITY),
+            history: Mutex::new(History {
+                next_sequence: 1,
+                events: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: Event) {
+        let mut history = self.history.lock().unwrap();
+
+        let event = SequencedEvent {
+            sequence: history.next_sequence,
+            event,
+        };
+        history.next_sequence += 1;
+
+        history.events.push_back(event.clone());
+        if history.events.len() > EVENT_HISTORY_LENGTH {
+            history.events.pop_front();
+        }
+
+        // Sending only fails without subscribers.
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes to the events after the given event ID, subscribe before reading the state to
+    /// miss none.
+    pub fn subscribe(&self, last_event_id: Option<&str>) -> Subscription {
+        let history = self.history.lock().unwrap();
+        let last_sequence = history.next_sequence - 1;
+
+        let missed = last_event_id
+            .and_then(|id| self.parse_id(id))
+            .filter(|sequence| *sequence <= last_sequence)
+            .filter(|sequence| {
+                let oldest = history
+                    .events
+                    .front()
+                    .map_or(history.next_sequence, |e| e.sequence);
+
+                oldest <= sequence + 1
+            })
+            .map(|sequence| {
+                history
+                    .events
+                    .iter()
+                    .filter(|e| e.sequence > sequence)
+                    .cloned()
+                    .collect()
+            });
+
+        Subscription {
+            receiver: self.sender.subscribe(),
+            missed,
+            last_sequence,
+        }
+    }
+
+    /// ID of an event as `<run>-<sequence>`, which is only valid within the same run.
+    pub fn event_id(&self, sequence: u64) -> String {
+        format!("{}-{}", self.run, sequence)
+    }
+
+    fn parse_id(&self, id: &str) -> Option<u64> {
+        let (run, sequence) = id.split_once('-')?;
+
+        (run.parse::<u64>().ok()? == self.run)
+            .then(|| sequence.parse().ok())
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus(events: u64) -> EventBus {
+        let bus = EventBus::default();
+        for fingerprint in 0..events {
+            bus.publish(Event::DeviceExpired { fingerprint });
+        }
+
+        bus
+    }
+
+    fn missed(bus: &EventBus, last_event_id: Option<&str>) -> Option<Vec<u64>> {
+        bus.subscribe(last_event_id)
+            .missed
+            .map(|events| events.iter().map(|e| e.sequence).collect())
+    }
+
+    #[test]
+    fn resumes_after_the_last_event() {
+        let bus = bus(5);
+
+        assert_eq!(bus.subscribe(None).last_sequence, 5);
+        assert_eq!(missed(&bus, None), None);
+        assert_eq!(missed(&bus, Some(&bus.event_id(5))), Some(vec![]));
+        assert_eq!(missed(&bus, Some(&bus.event_id(3))), Some(vec![4, 5]));
+        // Before the first event, which is still buffered.
+        assert_eq!(
+            missed(&bus, Some(&bus.event_id(0))),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+    }
+
+    #[test]
+    fn requires_a_snapshot_once_missed_events_are_evicted() {
+        let bus = bus(EVENT_HISTORY_LENGTH as u64 + 2);
+
+        // Events 1 and 2 are evicted, the oldest buffered event is 3.
+        let missed_after_2 = missed(&bus, Some(&bus.event_id(2))).unwrap();
+        assert_eq!(missed_after_2.len(), EVENT_HISTORY_LENGTH);
+        assert_eq!(missed_after_2.first(), Some(&3));
+
+        assert_eq!(missed(&bus, Some(&bus.event_id(1))), None);
+        assert_eq!(missed(&bus, Some(&bus.event_id(0))), None);
+    }
+
+    #[test]
+    fn requires_a_snapshot_for_ids_of_other_runs() {
+        let bus = bus(5);
+
+        let foreign = format!("{}-3", bus.run + 1);
+        assert_eq!(missed(&bus, Some(&foreign)), None);
+
+        // IDs from the future were issued by another run as well.
+        assert_eq!(missed(&bus, Some(&bus.event_id(6))), None);
+
+        assert_eq!(missed(&bus, Some("3")), None);
+        assert_eq!(missed(&bus, Some(&format!("{}-x", bus.run))), None);
+    }
+
+    #[test]
+    fn numbers_the_events_from_1() {
+        let bus = bus(0);
+        let mut subscription = bus.subscribe(None);
+        assert_eq!(subscription.last_sequence, 0);
+        assert_eq!(missed(&bus, Some(&bus.event_id(0))), Some(vec![]));
+
+        bus.publish(Event::SensorRemoved { id: 1 });
+
+        let event = subscription.receiver.try_recv().unwrap();
+        assert_eq!(event.sequence, 1);
+        assert_eq!(event.event.name(), "sensor_removed");
+    }
+}
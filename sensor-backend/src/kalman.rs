@@ -0,0 +1,139 @@
+/// One dimensional Kalman filter which models the RSSI of a single fingerprint at a single sensor
+/// as a random walk.
+#[derive(Clone)]
+pub struct KalmanFilter {
+    estimate: f64,
+    error_covariance: f64,
+    process_noise: f64,
+    measurement_noise: f64,
+    /// Milliseconds since the unix epoch.
+    last_update: u64,
+    updates: usize,
+}
+
+impl KalmanFilter {
+    pub fn new(
+        process_noise: f64,
+        measurement_noise: f64,
+        initial_measurement: f64,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            estimate: initial_measurement,
+            error_covariance: measurement_noise,
+            process_noise,
+            measurement_noise,
+            last_update: timestamp,
+            updates: 1,
+        }
+    }
+
+    pub fn update(&mut self, measurement: f64, timestamp: u64) -> f64 {
+        // The process noise is given per second, so the prediction gets less certain the longer
+        // the fingerprint has not been heard by the sensor.
+        let elapsed = timestamp.saturating_sub(self.last_update) as f64 / 1000.0;
+        self.error_covariance += self.process_noise * elapsed;
+
+        let gain = self.error_covariance / (self.error_covariance + self.measurement_noise);
+
+        self.estimate += gain * (measurement - self.estimate);
+        self.error_covariance *= 1.0 - gain;
+        self.last_update = timestamp;
+        self.updates += 1;
+
+        self.estimate
+    }
+
+    pub fn estimate(&self) -> f64 {
+        self.estimate
+    }
+
+    pub fn std(&self) -> f64 {
+        self.error_covariance.sqrt()
+    }
+
+    pub fn updates(&self) -> usize {
+        self.updates
+    }
+
+    pub fn last_update(&self) -> u64 {
+        self.last_update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROCESS_NOISE: f64 = 0.5;
+    const MEASUREMENT_NOISE: f64 = 4.0;
+
+    #[test]
+    fn starts_at_the_first_sample() {
+        let filter = KalmanFilter::new(PROCESS_NOISE, MEASUREMENT_NOISE, -63.0, 1_000);
+
+        assert_eq!(filter.estimate(), -63.0);
+        assert_eq!(filter.std(), MEASUREMENT_NOISE.sqrt());
+        assert_eq!(filter.updates(), 1);
+        assert_eq!(filter.last_update(), 1_000);
+    }
+
+    #[test]
+    fn converges_on_constant_input() {
+        let mut filter = KalmanFilter::new(PROCESS_NOISE, MEASUREMENT_NOISE, -90.0, 0);
+
+        for i in 1..=100 {
+            filter.update(-60.0, i * 1_000);
+        }
+
+        assert!(
+            (filter.estimate() + 60.0).abs() < 1e-3,
+            "estimate: {}",
+            filter.estimate()
+        );
+        assert_eq!(filter.updates(), 101);
+    }
+
+    #[test]
+    fn follows_the_measurement_faster_with_more_process_noise() {
+        let mut steady = KalmanFilter::new(0.01, MEASUREMENT_NOISE, -80.0, 0);
+        let mut agile = KalmanFilter::new(10.0, MEASUREMENT_NOISE, -80.0, 0);
+
+        let steady = steady.update(-60.0, 1_000);
+        let agile = agile.update(-60.0, 1_000);
+
+        assert!(
+            agile < -60.0 && agile > steady,
+            "steady: {steady}, agile: {agile}"
+        );
+    }
+
+    #[test]
+    fn follows_the_measurement_slower_with_more_measurement_noise() {
+        let mut trusting = KalmanFilter::new(PROCESS_NOISE, 1.0, -80.0, 0);
+        let mut sceptical = KalmanFilter::new(PROCESS_NOISE, 25.0, -80.0, 0);
+
+        for i in 1..=3 {
+            trusting.update(-60.0, i * 1_000);
+            sceptical.update(-60.0, i * 1_000);
+        }
+
+        assert!(
+            trusting.estimate() > sceptical.estimate(),
+            "trusting: {}, sceptical: {}",
+            trusting.estimate(),
+            sceptical.estimate()
+        );
+    }
+
+    #[test]
+    fn grows_uncertain_while_a_fingerprint_is_not_heard() {
+        let mut recent = KalmanFilter::new(PROCESS_NOISE, MEASUREMENT_NOISE, -70.0, 0);
+        let mut stale = recent.clone();
+
+        recent.update(-70.0, 1_000);
+        stale.update(-70.0, 60_000);
+
+        assert!(stale.std() > recent.std());
+    }
+}
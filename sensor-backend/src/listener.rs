@@ -1,51 +1,388 @@
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use sensor_lib::SensorPacket;
+use sensor_lib::SensorMessage;
+use sensor_lib::protocol::{Frame, MAX_FRAME_SIZE, decode_frame};
 use tokio::net::UdpSocket;
-use tracing::{error, info};
+use tokio::time::{Instant, sleep_until};
+use tracing::{debug, error, info};
 
-use crate::sensors::SensorService;
+use crate::recorder::{Record, Recorder, read_recording};
+use crate::sensors::{SensorService, unix_millis};
+use crate::{DEVICE_EXPIRY_INTERVAL, STORAGE_PRUNE_INTERVAL};
 
-pub async fn run_packet_listener(sensor_service: Arc<SensorService>) -> anyhow::Result<()> {
-    let socket = UdpSocket::bind("0.0.0.0:3000").await?;
+pub async fn run_packet_listener(
+    sensor_service: Arc<SensorService>,
+    bind: SocketAddr,
+    recorder: Option<Recorder>,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(bind).await?;
     info!("Running UDP listener on {}", socket.local_addr()?);
 
-    let mut buf = [0u8; 1024];
+    let mut buf = [0u8; MAX_FRAME_SIZE];
 
     loop {
-        let (len, _) = socket.recv_from(&mut buf).await?;
+        let (len, addr) = socket.recv_from(&mut buf).await?;
 
         let data = &buf[..len];
+        let timestamp = unix_millis();
 
-        match postcard::from_bytes::<SensorPacket>(data) {
-            Ok(packet) => {
-                info!(
-                    "Received packet from sensor {} (Y: {}, X: {}, Latitude: {}, Longitude: {}): RSSI {}, Fingerprint {}",
-                    packet.sensor_id,
-                    packet.y,
-                    packet.x,
-                    packet.latitude,
-                    packet.longitude,
-                    packet.rssi,
-                    packet.fingerprint
-                );
+        if let Some(recorder) = &recorder {
+            recorder.record(Record {
+                timestamp,
+                source: addr,
+                data: data.to_vec(),
+            });
+        }
 
-                sensor_service
-                    .add_sensor(
-                        packet.sensor_id,
-                        packet.y,
-                        packet.x,
-                        packet.latitude,
-                        packet.longitude,
-                        packet.environment,
-                    )
-                    .await;
+        handle_datagram(&sensor_service, data, addr, timestamp).await;
+    }
+}
+
+/// Feeds a recording through the same pipeline as received datagrams. Every datagram is handled
+/// with its recorded arrival time and the devices expire in the time of the recording, so the
+/// results do not depend on the playback speed.
+pub async fn replay_recording(
+    sensor_service: Arc<SensorService>,
+    path: &Path,
+    speed: f64,
+) -> anyhow::Result<()> {
+    let records = read_recording(path)?;
+    info!(
+        "Replaying {} datagrams from {}",
+        records.len(),
+        path.display()
+    );
+
+    let Some(first) = records.first().map(|r| r.timestamp) else {
+        return Ok(());
+    };
+
+    let start = Instant::now();
+    let mut next_expiry = first;
+    let mut next_prune = first;
+
+    for record in &records {
+        if speed > 0.0 {
+            let offset =
+                Duration::from_millis(record.timestamp.saturating_sub(first)).div_f64(speed);
+            sleep_until(start + offset).await;
+        }
+
+        if record.timestamp >= next_expiry {
+            sensor_service.expire_devices(record.timestamp).await;
+            next_expiry = record.timestamp + DEVICE_EXPIRY_INTERVAL.as_millis() as u64;
+        }
+        if record.timestamp >= next_prune {
+            sensor_service.prune_storage(record.timestamp).await;
+            next_prune = record.timestamp + STORAGE_PRUNE_INTERVAL.as_millis() as u64;
+        }
+
+        handle_datagram(
+            &sensor_service,
+            &record.data,
+            record.source,
+            record.timestamp,
+        )
+        .await;
+    }
+
+    info!(
+        "Replayed {} datagrams in {:.1}s",
+        records.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
+
+/// Handles a datagram received at the given time in milliseconds since the unix epoch.
+async fn handle_datagram(
+    sensor_service: &SensorService,
+    data: &[u8],
+    addr: SocketAddr,
+    timestamp: u64,
+) {
+    let frame =
+        decode_frame::<SensorMessage>(data, |sensor_id| sensor_service.sensor_key(sensor_id));
+    let Frame { header, payload } = match frame {
+        Ok(frame) => frame,
+        Err(e) => {
+            error!("Rejected frame from {}: {}", addr, e);
+            sensor_service.record_rejection(&e).await;
+            return;
+        }
+    };
+
+    if !sensor_service.check_replay(&header, timestamp).await {
+        error!(
+            "Rejected replayed frame #{} of epoch {} from {} for sensor {}",
+            header.sequence, header.epoch, addr, header.sensor_id
+        );
+        return;
+    }
 
+    sensor_service.record_frame(&header, timestamp).await;
+
+    match payload {
+        SensorMessage::Hello(info) | SensorMessage::Heartbeat(info)
+            if info.sensor_id != header.sensor_id =>
+        {
+            error!(
+                "Rejected sensor info for sensor {} in frame of sensor {}",
+                info.sensor_id, header.sensor_id
+            );
+        }
+        SensorMessage::Hello(info) => {
+            info!(
+                "Sensor {} (firmware {}) started at Y: {}, X: {}, Latitude: {}, Longitude: {}",
+                info.sensor_id,
+                info.firmware_version,
+                info.y,
+                info.x,
+                info.latitude,
+                info.longitude
+            );
+
+            sensor_service.add_sensor(&info, timestamp).await;
+        }
+        SensorMessage::Heartbeat(info) => {
+            debug!("Received heartbeat from sensor {}", info.sensor_id);
+
+            sensor_service.add_sensor(&info, timestamp).await;
+        }
+        SensorMessage::Measurement(measurement) => {
+            info!(
+                "Received measurement #{} from sensor {}: RSSI {}, Fingerprint {}",
+                header.sequence, header.sensor_id, measurement.rssi, measurement.fingerprint
+            );
+
+            sensor_service
+                .add_measurement(header.sensor_id, &measurement, timestamp)
+                .await;
+        }
+        SensorMessage::MeasurementBatch(batch) => {
+            info!(
+                "Received batch #{} of {} measurements from sensor {}",
+                header.sequence,
+                batch.len(),
+                header.sensor_id
+            );
+
+            for measurement in batch.measurements {
                 sensor_service
-                    .add_measurement(packet.fingerprint, packet.sensor_id, packet.rssi)
+                    .add_measurement(header.sensor_id, &measurement, timestamp)
                     .await;
             }
-            Err(e) => error!("Failed to deserialize packet: {}", e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sensor_lib::protocol::{FrameHeader, encode_frame};
+    use sensor_lib::{Environment, Measurement, MeasurementBatch, SensorInfo};
+
+    use super::*;
+    use crate::auth::SensorKeyConfig;
+    use crate::config::Config;
+
+    const KEY: [u8; 32] = [7; 32];
+    const START: u64 = 1_700_000_000_000;
+
+    fn frame(sensor_id: u8, sequence: u32, message: SensorMessage) -> Vec<u8> {
+        let frame = Frame {
+            header: FrameHeader {
+                sensor_id,
+                epoch: 1,
+                sequence,
+                uptime_ms: 0,
+            },
+            payload: message,
+        };
+
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        encode_frame(&frame, &KEY, &mut buf).unwrap().to_vec()
+    }
+
+    /// Three sensors that see a device walking away from the first one, with the address of the
+    /// device in every round.
+    fn walk(address: impl Fn(u32) -> u64) -> Vec<(u64, Vec<u8>)> {
+        let sensors = [(1, 0.0, 0.0), (2, 0.0, 4.0), (3, 3.0, 2.0)];
+        let mut datagrams = Vec::new();
+
+        for (sensor_id, y, x) in sensors {
+            let info = SensorInfo {
+                sensor_id,
+                y,
+                x,
+                latitude: 50.5648 + y * 1e-5,
+                longitude: 9.6845 + x * 1e-5,
+                environment: Environment::FreeSpace,
+                firmware_version: "test".try_into().unwrap(),
+            };
+
+            datagrams.push((START, frame(sensor_id, 0, SensorMessage::Hello(info))));
+        }
+
+        for round in 0..6u32 {
+            for (sensor_id, _, _) in sensors {
+                let mut batch = MeasurementBatch::new();
+
+                for i in 0..10 {
+                    batch
+                        .push(Measurement {
+                            sensor_id,
+                            fingerprint: address(round),
+                            signature: None,
+                            sequence_number: Some((round * 10 + i) as u16),
+                            rssi: -45 - (round * sensor_id as u32 + i % 3) as i8,
+                            timestamp_ms: 0,
+                        })
+                        .unwrap();
+                }
+
+                let timestamp = START + round as u64 * 2000 + sensor_id as u64 * 10;
+                datagrams.push((
+                    timestamp,
+                    frame(sensor_id, round + 1, SensorMessage::MeasurementBatch(batch)),
+                ));
+            }
+        }
+
+        datagrams
+    }
+
+    fn datagrams() -> Vec<(u64, Vec<u8>)> {
+        let mut datagrams = walk(|_| 0xabc123);
+
+        // A replayed frame and garbage, both rejected.
+        let replayed = datagrams[3].1.clone();
+        datagrams.push((START + 20_000, replayed));
+        datagrams.push((START + 20_001, vec![0; 8]));
+
+        datagrams
+    }
+
+    fn service() -> Arc<SensorService> {
+        let mut config = Config::default();
+        config.auth.keys = (1..=3)
+            .map(|sensor_id| SensorKeyConfig {
+                sensor_id,
+                key: KEY,
+            })
+            .collect();
+
+        Arc::new(SensorService::new(&config, None).unwrap())
+    }
+
+    async fn replay(path: &Path) -> serde_json::Value {
+        let sensor_service = service();
+        replay_recording(sensor_service.clone(), path, 0.0)
+            .await
+            .unwrap();
+
+        let mut trilaterations = sensor_service.get_trilaterations().await;
+        trilaterations.sort_by_key(|t| t.fingerprint);
+
+        serde_json::to_value(trilaterations).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_recordings_deterministically() {
+        let path = std::env::temp_dir().join(format!("replay-{}.bin", std::process::id()));
+        let source: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        let datagrams = datagrams();
+        let recorder = Recorder::create(&path).unwrap();
+        for (timestamp, data) in &datagrams {
+            recorder.record(Record {
+                timestamp: *timestamp,
+                source,
+                data: data.clone(),
+            });
+        }
+        drop(recorder);
+
+        let records = read_recording(&path).unwrap();
+        assert_eq!(records.len(), datagrams.len());
+        assert!(
+            records
+                .iter()
+                .zip(&datagrams)
+                .all(|(r, (timestamp, data))| r.timestamp == *timestamp && r.data == *data)
+        );
+
+        let first = replay(&path).await;
+        let second = replay(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(first.as_array().map(Vec::len), Some(1));
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn counts_replayed_frames_as_duplicates() {
+        let sensor_service = service();
+        let source: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        for (timestamp, data) in datagrams() {
+            handle_datagram(&sensor_service, &data, source, timestamp).await;
+        }
+
+        let stats = sensor_service.get_packet_stats().await;
+        let stats = stats.iter().find(|s| s.sensor_id == 1).unwrap();
+
+        assert_eq!(stats.received, 7);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(stats.last_seen, START + 20_000);
+        assert_eq!(sensor_service.get_rejection_stats().await.replayed, 1);
+    }
+
+    #[tokio::test]
+    async fn looks_up_tracks_by_any_address_of_a_device() {
+        const FIRST: u64 = 0x02 << 56 | 1;
+        const SECOND: u64 = 0x02 << 56 | 2;
+
+        let sensor_service = service();
+        let source: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        // The device rotates its randomized address halfway, the sequence number continues.
+        for (timestamp, data) in walk(|round| if round < 3 { FIRST } else { SECOND }) {
+            handle_datagram(&sensor_service, &data, source, timestamp).await;
+        }
+
+        let (id, track) = sensor_service.get_track(SECOND, None, None).await.unwrap();
+
+        assert_eq!(id, FIRST);
+        assert_eq!(track.len(), 6);
+        assert!(track.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(track.last().unwrap().members, vec![FIRST, SECOND]);
+
+        let (_, first) = sensor_service.get_track(FIRST, None, None).await.unwrap();
+        assert_eq!(first.len(), track.len());
+    }
+
+    #[tokio::test]
+    async fn rejects_replayed_frames_of_removed_sensors() {
+        let sensor_service = service();
+        let source: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+
+        let datagrams = walk(|_| 0xabc123);
+        for (timestamp, data) in &datagrams {
+            handle_datagram(&sensor_service, data, source, *timestamp).await;
+        }
+
+        assert!(sensor_service.remove_sensor(1).await.is_some());
+
+        // The captured Hello of the sensor would register it again at its old position.
+        let (_, hello) = &datagrams[0];
+        handle_datagram(&sensor_service, hello, source, START + 30_000).await;
+
+        assert!(sensor_service.get_sensor(1).await.is_none());
+        assert_eq!(sensor_service.get_rejection_stats().await.replayed, 1);
+    }
+}
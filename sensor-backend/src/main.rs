@@ -1,22 +1,47 @@
+use std::future::pending;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::Router;
 use tokio::net::TcpListener;
+use tokio::signal;
+use tokio::time::interval;
 use tower_http::cors::CorsLayer;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use utoipa_swagger_ui::SwaggerUi;
 
 use crate::api::api;
-use crate::listener::run_packet_listener;
-use crate::sensors::SensorService;
+use crate::config::Config;
+use crate::listener::{replay_recording, run_packet_listener};
+use crate::recorder::Recorder;
+use crate::sensors::{SensorService, unix_millis};
+use crate::storage::{SqliteStorage, Storage};
 
 mod api;
+mod auth;
+mod calibration;
+mod clustering;
+mod config;
 mod coords;
+mod events;
+mod kalman;
 mod listener;
+mod path_loss;
+mod recorder;
+mod registry;
 mod rssi;
+mod schema;
 mod sensors;
+mod stats;
+mod storage;
+mod tracking;
+
+const STORAGE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DEVICE_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub struct AppState {
+    config: Arc<Config>,
     sensor_service: Arc<SensorService>,
 }
 
@@ -24,24 +49,130 @@ pub struct AppState {
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let sensor_service = Arc::new(SensorService::new());
+    let config = Config::load()?;
+
+    // A replay starts from a clean state and leaves the database alone, otherwise the replayed
+    // positions were mixed with the stored ones.
+    let storage: Option<Arc<dyn Storage>> = match &config.storage.database_path {
+        Some(path) if config.replay.is_some() => {
+            warn!("Ignoring database {} during the replay", path.display());
+            None
+        }
+        Some(path) => {
+            info!("Using database {}", path.display());
+            Some(Arc::new(SqliteStorage::open(path)?))
+        }
+        None => None,
+    };
+
+    let sensor_service = Arc::new(SensorService::new(&config, storage)?);
+
+    // A replay expires the devices in the time of the recording instead.
+    if config.replay.is_none() {
+        let sensor_service_clone = sensor_service.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(STORAGE_PRUNE_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                sensor_service_clone.prune_storage(unix_millis()).await;
+            }
+        });
+
+        let sensor_service_clone = sensor_service.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(DEVICE_EXPIRY_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                sensor_service_clone.expire_devices(unix_millis()).await;
+            }
+        });
+    }
 
     let sensor_service_clone = sensor_service.clone();
-    tokio::spawn(async {
-        if let Err(e) = run_packet_listener(sensor_service_clone).await {
-            error!("Failed to run UDP listener: {}", e);
+    match config.replay.clone() {
+        Some(replay) => {
+            tokio::spawn(async move {
+                if let Err(e) =
+                    replay_recording(sensor_service_clone, &replay.path, replay.speed).await
+                {
+                    error!("Failed to replay {}: {}", replay.path.display(), e);
+                }
+            });
+        }
+        None => {
+            let recorder = match &config.server.record_path {
+                Some(path) => {
+                    info!("Recording datagrams to {}", path.display());
+                    Some(Recorder::create(path)?)
+                }
+                None => None,
+            };
+
+            let udp_bind = config.server.udp_bind;
+            tokio::spawn(async move {
+                if let Err(e) = run_packet_listener(sensor_service_clone, udp_bind, recorder).await
+                {
+                    error!("Failed to run UDP listener: {}", e);
+                }
+            });
         }
-    });
+    }
 
-    let state = AppState { sensor_service };
+    let listener = TcpListener::bind(config.server.http_bind).await?;
+
+    let state = AppState {
+        config: Arc::new(config),
+        sensor_service,
+    };
+
+    let (api, openapi) = api(state);
 
     let app = Router::new()
-        .nest("/api", api(state))
+        .nest("/api", api)
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", openapi))
         .layer(CorsLayer::permissive());
 
-    let listener = TcpListener::bind("0.0.0.0:8080").await?;
-
     info!("Starting HTTP server on {}", listener.local_addr()?);
 
-    Ok(axum::serve(listener, app).await?)
+    // Open event streams never end, so the server is not shut down gracefully. Returning shuts
+    // down the runtime instead, which drops the tasks with the last references to the sensor
+    // service and the recorder, so that their queued writes are flushed before the process exits.
+    tokio::select! {
+        result = axum::serve(listener, app) => result?,
+        _ = shutdown_signal() => info!("Shutting down"),
+    }
+
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
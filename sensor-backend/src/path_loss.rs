@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const MIN_FIT_SAMPLES: usize = 10;
+pub const MAX_FIT_SAMPLES: usize = 500;
+
+const MIN_REFERENCE_DISTANCE: f64 = 0.1;
+
+/// Smallest change of the exponent or the goodness-of-fit for which a new fit replaces the
+/// published one, so that every captured sample does not cause a write and an event.
+const MIN_FIT_CHANGE: f64 = 0.01;
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReferenceTransmitter {
+    pub fingerprint: u64,
+    #[serde(default)]
+    pub x: f64,
+    #[serde(default)]
+    pub y: f64,
+    /// Follows the position of the given sensor node instead of `x` and `y`.
+    #[serde(default)]
+    pub sensor_id: Option<u8>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PathLossFit {
+    pub exponent: f64,
+    pub r_squared: f64,
+    pub rmse: f64,
+    pub samples: usize,
+}
+
+impl PathLossFit {
+    pub fn differs_from(&self, other: &PathLossFit) -> bool {
+        (self.exponent - other.exponent).abs() >= MIN_FIT_CHANGE
+            || (self.r_squared - other.r_squared).abs() >= MIN_FIT_CHANGE
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PathLossSample {
+    pub distance: f64,
+    pub rssi: f64,
+}
+
+impl PathLossSample {
+    pub fn new(distance: f64, rssi: f64) -> Option<Self> {
+        (distance >= MIN_REFERENCE_DISTANCE).then_some(Self { distance, rssi })
+    }
+}
+
+/// Fits the exponent `n` of `rssi = rssi_at_1m - 10 * n * log10(d)` by least squares with the
+/// reference RSSI fixed to the calibrated value of the sensor.
+pub fn fit_path_loss(samples: &VecDeque<PathLossSample>, rssi_at_1m: f64) -> Option<PathLossFit> {
+    if samples.len() < MIN_FIT_SAMPLES {
+        return None;
+    }
+
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|s| (10.0 * s.distance.log10(), rssi_at_1m - s.rssi))
+        .collect();
+
+    // Samples taken at a single distance cannot tell the exponent apart from a miscalibrated
+    // reference RSSI.
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / points.len() as f64;
+    let spread: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if spread <= f64::EPSILON {
+        return None;
+    }
+
+    let sxx: f64 = points.iter().map(|(x, _)| x * x).sum();
+
+    let sxy: f64 = points.iter().map(|(x, y)| x * y).sum();
+    let exponent = sxy / sxx;
+
+    let mean = points.iter().map(|(_, y)| y).sum::<f64>() / points.len() as f64;
+    let ss_res: f64 = points.iter().map(|(x, y)| (y - exponent * x).powi(2)).sum();
+    let ss_tot: f64 = points.iter().map(|(_, y)| (y - mean).powi(2)).sum();
+
+    let r_squared = if ss_tot > f64::EPSILON {
+        1.0 - ss_res / ss_tot
+    } else {
+        0.0
+    };
+
+    Some(PathLossFit {
+        exponent,
+        r_squared,
+        rmse: (ss_res / points.len() as f64).sqrt(),
+        samples: points.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSSI_AT_1M: f64 = -45.0;
+
+    fn samples(exponent: f64, distances: &[f64]) -> VecDeque<PathLossSample> {
+        distances
+            .iter()
+            .map(|&d| PathLossSample::new(d, RSSI_AT_1M - 10.0 * exponent * d.log10()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn recovers_a_known_exponent() {
+        let distances: Vec<f64> = (1..=20).map(|i| i as f64 * 0.75).collect();
+
+        let fit = fit_path_loss(&samples(2.7, &distances), RSSI_AT_1M).unwrap();
+
+        assert!(
+            (fit.exponent - 2.7).abs() < 1e-9,
+            "exponent: {}",
+            fit.exponent
+        );
+        assert!((fit.r_squared - 1.0).abs() < 1e-9, "r²: {}", fit.r_squared);
+        assert!(fit.rmse < 1e-9, "rmse: {}", fit.rmse);
+        assert_eq!(fit.samples, distances.len());
+    }
+
+    #[test]
+    fn fits_noisy_samples_worse() {
+        let distances: Vec<f64> = (1..=20).map(|i| i as f64).collect();
+        let mut samples = samples(3.0, &distances);
+        for (i, sample) in samples.iter_mut().enumerate() {
+            sample.rssi += if i % 2 == 0 { 3.0 } else { -3.0 };
+        }
+
+        let fit = fit_path_loss(&samples, RSSI_AT_1M).unwrap();
+
+        assert!(
+            (fit.exponent - 3.0).abs() < 0.1,
+            "exponent: {}",
+            fit.exponent
+        );
+        assert!(fit.r_squared < 1.0);
+        assert!((fit.rmse - 3.0).abs() < 0.1, "rmse: {}", fit.rmse);
+    }
+
+    #[test]
+    fn requires_enough_samples() {
+        let distances: Vec<f64> = (1..MIN_FIT_SAMPLES).map(|i| i as f64).collect();
+
+        assert!(fit_path_loss(&samples(2.0, &distances), RSSI_AT_1M).is_none());
+    }
+
+    #[test]
+    fn requires_more_than_one_distance() {
+        let distances = [4.0; MIN_FIT_SAMPLES * 2];
+
+        assert!(fit_path_loss(&samples(2.0, &distances), RSSI_AT_1M).is_none());
+    }
+
+    #[test]
+    fn rejects_references_too_close_to_the_sensor() {
+        assert!(PathLossSample::new(0.05, -40.0).is_none());
+    }
+
+    #[test]
+    fn ignores_small_changes_of_the_fit() {
+        let fit = PathLossFit {
+            exponent: 2.5,
+            r_squared: 0.9,
+            rmse: 2.0,
+            samples: 50,
+        };
+
+        let resampled = PathLossFit {
+            exponent: 2.505,
+            samples: 51,
+            ..fit.clone()
+        };
+        let moved = PathLossFit {
+            exponent: 2.6,
+            ..fit.clone()
+        };
+
+        assert!(!resampled.differs_from(&fit));
+        assert!(moved.differs_from(&fit));
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+/// Identifies recordings, followed by the format version.
+const RECORDING_MAGIC: [u8; 4] = *b"SREC";
+const RECORDING_VERSION: u8 = 1;
+
+/// A datagram as it was received by the packet listener.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub source: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+/// Appends every received datagram to a recording, each record postcard encoded and prefixed
+/// with its length. The records are written on a dedicated thread, so that the listener never
+/// waits for the disk.
+pub struct Recorder {
+    sender: Option<Sender<Record>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open recording {}: {}", path.display(), e))?;
+
+        if file.metadata()?.len() == 0 {
+            file.write_all(&RECORDING_MAGIC)?;
+            file.write_all(&[RECORDING_VERSION])?;
+        }
+
+        let (sender, receiver) = mpsc::channel::<Record>();
+        let mut writer = BufWriter::new(file);
+
+        let writer = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || {
+                while let Ok(record) = receiver.recv() {
+                    // Keeps the recording complete up to the last datagram if the backend
+                    // crashes, but writes the records that queued up meanwhile at once.
+                    let result = std::iter::once(record)
+                        .chain(receiver.try_iter())
+                        .try_for_each(|record| write_record(&mut writer, &record))
+                        .and_then(|()| Ok(writer.flush()?));
+
+                    if let Err(e) = result {
+                        error!("Failed to record datagram: {}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn record(&self, record: Record) {
+        if let Some(sender) = &self.sender
+            && sender.send(record).is_err()
+        {
+            error!("Failed to record datagram: recorder stopped");
+        }
+    }
+}
+
+/// Waits until all queued records are written.
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        if let Some(writer) = self.writer.take()
+            && writer.join().is_err()
+        {
+            error!("Recorder thread panicked");
+        }
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &Record) -> anyhow::Result<()> {
+    let data = postcard::to_stdvec(record)?;
+
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(&data)?;
+
+    Ok(())
+}
+
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open recording {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+
+    let mut preamble = [0u8; RECORDING_MAGIC.len() + 1];
+    reader.read_exact(&mut preamble)?;
+
+    if preamble[..RECORDING_MAGIC.len()] != RECORDING_MAGIC {
+        anyhow::bail!("{} is not a recording", path.display());
+    }
+    if preamble[RECORDING_MAGIC.len()] != RECORDING_VERSION {
+        anyhow::bail!(
+            "Unsupported recording version {} (supported: {})",
+            preamble[RECORDING_MAGIC.len()],
+            RECORDING_VERSION
+        );
+    }
+
+    let mut records = Vec::new();
+    let mut len = [0u8; 4];
+
+    loop {
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+        match reader.read_exact(&mut data) {
+            Ok(()) => {}
+            // The backend stopped while writing the last record.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                warn!("Ignoring truncated last record of {}", path.display());
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        records.push(postcard::from_bytes(&data)?);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_a_truncated_last_record() {
+        let path = std::env::temp_dir().join(format!("recording-{}.bin", std::process::id()));
+
+        let recorder = Recorder::create(&path).unwrap();
+        for timestamp in 0..3 {
+            recorder.record(Record {
+                timestamp,
+                source: "127.0.0.1:3000".parse().unwrap(),
+                data: vec![timestamp as u8; 16],
+            });
+        }
+        drop(recorder);
+
+        assert_eq!(read_recording(&path).unwrap().len(), 3);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 4).unwrap();
+
+        let records = read_recording(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].timestamp, 1);
+        assert_eq!(records[1].data, vec![1; 16]);
+    }
+}
@@ -0,0 +1,369 @@
+use sensor_lib::Environment;
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+
+use crate::sensors::Sensor;
+
+pub fn default_enabled() -> bool {
+    true
+}
+
+/// The position and settings of a sensor as registered by an operator.
+#[derive(Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SensorRegistration {
+    pub y: f64,
+    pub x: f64,
+    pub lat: f64,
+    pub lon: f64,
+    #[schema(value_type = crate::schema::Environment)]
+    pub environment: Environment,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl SensorRegistration {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_position(Some(self.y), Some(self.x), Some(self.lat), Some(self.lon))
+    }
+
+    /// Applies the registration to the sensor, which resolves a conflicting reported position.
+    pub fn apply(self, sensor: &mut Sensor) {
+        sensor.y = self.y;
+        sensor.x = self.x;
+        sensor.lat = self.lat;
+        sensor.lon = self.lon;
+        sensor.environment = self.environment;
+        sensor.label = self.label;
+        sensor.enabled = self.enabled;
+        sensor.conflict = None;
+    }
+}
+
+/// Changes to a registered sensor, fields that are missing stay unchanged.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SensorPatch {
+    y: Option<f64>,
+    x: Option<f64>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    #[schema(value_type = Option<crate::schema::Environment>)]
+    environment: Option<Environment>,
+    /// `null` removes the label.
+    #[serde(default, deserialize_with = "nullable")]
+    label: Option<Option<String>>,
+    enabled: Option<bool>,
+}
+
+fn nullable<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<String>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl SensorPatch {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_position(self.y, self.x, self.lat, self.lon)
+    }
+
+    pub fn apply(self, sensor: &mut Sensor) {
+        SensorRegistration {
+            y: self.y.unwrap_or(sensor.y),
+            x: self.x.unwrap_or(sensor.x),
+            lat: self.lat.unwrap_or(sensor.lat),
+            lon: self.lon.unwrap_or(sensor.lon),
+            environment: self.environment.unwrap_or(sensor.environment),
+            label: self.label.unwrap_or_else(|| sensor.label.clone()),
+            enabled: self.enabled.unwrap_or(sensor.enabled),
+        }
+        .apply(sensor);
+    }
+}
+
+fn validate_position(
+    y: Option<f64>,
+    x: Option<f64>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+) -> Result<(), String> {
+    if [y, x, lat, lon]
+        .into_iter()
+        .flatten()
+        .any(|v| !v.is_finite())
+    {
+        return Err("Coordinates must be finite".to_string());
+    }
+    if lat.is_some_and(|lat| lat.abs() > 90.0) {
+        return Err("Latitude must be between -90 and 90".to_string());
+    }
+    if lon.is_some_and(|lon| lon.abs() > 180.0) {
+        return Err("Longitude must be between -180 and 180".to_string());
+    }
+
+    Ok(())
+}
+
+/// A position reported by a sensor that differs from its registered position.
+#[derive(Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ReportedPosition {
+    pub y: f64,
+    pub x: f64,
+    pub lat: f64,
+    pub lon: f64,
+    #[schema(value_type = crate::schema::Environment)]
+    pub environment: Environment,
+    /// Time of the first report in milliseconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+impl ReportedPosition {
+    pub fn matches(&self, sensor: &Sensor) -> bool {
+        (self.y, self.x, self.lat, self.lon, self.environment)
+            == (
+                sensor.y,
+                sensor.x,
+                sensor.lat,
+                sensor.lon,
+                sensor.environment,
+            )
+    }
+
+    fn same_position(&self, other: &ReportedPosition) -> bool {
+        (self.y, self.x, self.lat, self.lon, self.environment)
+            == (other.y, other.x, other.lat, other.lon, other.environment)
+    }
+
+    /// Whether the report changes the recorded conflict of the sensor.
+    pub fn is_new_conflict(&self, sensor: &Sensor) -> bool {
+        !self.matches(sensor)
+            && sensor
+                .conflict
+                .as_ref()
+                .is_none_or(|conflict| !conflict.same_position(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sensor_lib::SensorInfo;
+    use sensor_lib::protocol::FrameHeader;
+
+    use super::*;
+    use crate::config::{Config, PacketPositionPolicy};
+    use crate::sensors::SensorService;
+
+    fn registration() -> SensorRegistration {
+        SensorRegistration {
+            y: 0.0,
+            x: 4.0,
+            lat: 50.5649,
+            lon: 9.6845,
+            environment: Environment::FreeSpace,
+            label: Some("Entrance".to_string()),
+            enabled: true,
+        }
+    }
+
+    fn sensor() -> Sensor {
+        let mut sensor = Sensor {
+            id: 1,
+            x: 0.0,
+            y: 0.0,
+            lat: 0.0,
+            lon: 0.0,
+            environment: Environment::UrbanArea,
+            rssi_at_1m: -70.0,
+            rssi_offset: 0.0,
+            path_loss: None,
+            label: None,
+            enabled: true,
+            conflict: None,
+        };
+        registration().apply(&mut sensor);
+
+        sensor
+    }
+
+    fn reported(x: f64, timestamp: u64) -> ReportedPosition {
+        ReportedPosition {
+            y: 0.0,
+            x,
+            lat: 50.5649,
+            lon: 9.6845,
+            environment: Environment::FreeSpace,
+            timestamp,
+        }
+    }
+
+    fn patch(json: &str) -> SensorPatch {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn detects_new_conflicts() {
+        let mut sensor = sensor();
+
+        // The registered position is no conflict.
+        assert!(reported(4.0, 0).matches(&sensor));
+        assert!(!reported(4.0, 0).is_new_conflict(&sensor));
+
+        assert!(reported(5.0, 0).is_new_conflict(&sensor));
+        sensor.conflict = Some(reported(5.0, 0));
+
+        // Repeating the recorded conflict, even later, changes nothing.
+        assert!(!reported(5.0, 1000).is_new_conflict(&sensor));
+        assert!(reported(6.0, 1000).is_new_conflict(&sensor));
+
+        let mut moved = reported(5.0, 1000);
+        moved.environment = Environment::ShadowedUrban;
+        assert!(moved.is_new_conflict(&sensor));
+    }
+
+    #[test]
+    fn patches_only_the_given_fields() {
+        let mut sensor = sensor();
+        sensor.conflict = Some(reported(5.0, 0));
+        sensor.rssi_at_1m = -60.0;
+
+        patch(r#"{"x": 2.5, "enabled": false}"#).apply(&mut sensor);
+
+        assert_eq!(sensor.x, 2.5);
+        assert_eq!(sensor.y, 0.0);
+        assert_eq!(sensor.lat, 50.5649);
+        assert_eq!(sensor.label.as_deref(), Some("Entrance"));
+        assert!(!sensor.enabled);
+        // A registration change resolves the conflict but keeps the calibration.
+        assert!(sensor.conflict.is_none());
+        assert_eq!(sensor.rssi_at_1m, -60.0);
+    }
+
+    #[test]
+    fn distinguishes_missing_and_null_labels() {
+        let mut sensor = sensor();
+
+        patch("{}").apply(&mut sensor);
+        assert_eq!(sensor.label.as_deref(), Some("Entrance"));
+
+        patch(r#"{"label": "Exit"}"#).apply(&mut sensor);
+        assert_eq!(sensor.label.as_deref(), Some("Exit"));
+
+        patch(r#"{"label": null}"#).apply(&mut sensor);
+        assert_eq!(sensor.label, None);
+    }
+
+    #[test]
+    fn validates_positions() {
+        assert!(registration().validate().is_ok());
+        assert!(patch(r#"{"lat": 90.0, "lon": -180.0}"#).validate().is_ok());
+        assert!(patch(r#"{"lat": 90.5}"#).validate().is_err());
+        assert!(patch(r#"{"lon": 180.5}"#).validate().is_err());
+        assert!(
+            SensorRegistration {
+                x: f64::NAN,
+                ..registration()
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(serde_json::from_str::<SensorPatch>(r#"{"id": 2}"#).is_err());
+    }
+
+    fn service(policy: PacketPositionPolicy) -> SensorService {
+        let mut config = Config::default();
+        config.sensors.packet_positions = policy;
+
+        SensorService::new(&config, None).unwrap()
+    }
+
+    fn info(x: f64) -> SensorInfo {
+        SensorInfo {
+            sensor_id: 1,
+            y: 0.0,
+            x,
+            latitude: 50.5649,
+            longitude: 9.6845,
+            environment: Environment::FreeSpace,
+            firmware_version: "test".try_into().unwrap(),
+        }
+    }
+
+    /// The registered sensor after a report at `x` = 5 of a sensor registered at `x` = 4,
+    /// `None` if it is not registered.
+    async fn report(policy: PacketPositionPolicy, registered: bool) -> Option<Sensor> {
+        let service = service(policy);
+        if registered {
+            service.create_sensor(1, registration()).await.unwrap();
+        }
+
+        service.add_sensor(&info(5.0), 1000).await;
+
+        service.get_sensor(1).await
+    }
+
+    #[tokio::test]
+    async fn applies_the_packet_position_policy() {
+        use PacketPositionPolicy::*;
+
+        let accepted = report(Accept, false).await.unwrap();
+        assert_eq!(accepted.x, 5.0);
+        let accepted = report(Accept, true).await.unwrap();
+        assert_eq!(accepted.x, 5.0);
+        assert_eq!(accepted.label.as_deref(), Some("Entrance"));
+        assert!(accepted.conflict.is_none());
+
+        assert!(report(Ignore, false).await.is_none());
+        let ignored = report(Ignore, true).await.unwrap();
+        assert_eq!(ignored.x, 4.0);
+        assert!(ignored.conflict.is_none());
+
+        assert!(report(Flag, false).await.is_none());
+        let flagged = report(Flag, true).await.unwrap();
+        assert_eq!(flagged.x, 4.0);
+        assert!(flagged.conflict == Some(reported(5.0, 1000)));
+    }
+
+    #[tokio::test]
+    async fn resolves_a_flagged_conflict_once_the_registered_position_is_reported() {
+        let service = service(PacketPositionPolicy::Flag);
+        service.create_sensor(1, registration()).await.unwrap();
+
+        service.add_sensor(&info(5.0), 1000).await;
+        service.add_sensor(&info(5.0), 2000).await;
+        let sensor = service.get_sensor(1).await.unwrap();
+        assert_eq!(sensor.conflict.map(|c| c.timestamp), Some(1000));
+
+        service.add_sensor(&info(4.0), 3000).await;
+        assert!(service.get_sensor(1).await.unwrap().conflict.is_none());
+    }
+
+    #[tokio::test]
+    async fn registers_deleted_sensors_again_only_when_accepting_reports() {
+        for (policy, recreated) in [
+            (PacketPositionPolicy::Accept, true),
+            (PacketPositionPolicy::Ignore, false),
+            (PacketPositionPolicy::Flag, false),
+        ] {
+            let service = service(policy);
+            service.create_sensor(1, registration()).await.unwrap();
+
+            let header = FrameHeader {
+                sensor_id: 1,
+                epoch: 1,
+                sequence: 0,
+                uptime_ms: 0,
+            };
+            service.record_frame(&header, 500).await;
+            assert!(service.check_replay(&header, 500).await);
+
+            assert!(service.remove_sensor(1).await.is_some());
+            assert!(service.remove_sensor(1).await.is_none());
+            assert!(service.get_packet_stats().await.is_empty());
+
+            service.add_sensor(&info(5.0), 1000).await;
+            assert_eq!(service.get_sensor(1).await.is_some(), recreated);
+        }
+    }
+}
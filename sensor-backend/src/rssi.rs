@@ -1,56 +1,377 @@
 use std::collections::VecDeque;
 
-use sensor_lib::Environment;
-use tokio::time::Instant;
+use nalgebra::{DMatrix, DVector, Matrix2, Vector2};
+use serde::{Deserialize, Serialize};
 use tracing::debug;
+use utoipa::ToSchema;
 
 use crate::sensors::SensorCandidate;
 
-const CALIBRATED_RSSI_AT_1M: f64 = -70.0;
+pub const MIN_TRILATERATION_SENSORS: usize = 3;
 
-fn rssi_to_distance(rssi: f64, environment: Environment) -> f64 {
-    let exponent = (CALIBRATED_RSSI_AT_1M - rssi) / (10.0 * environment.as_f64());
+const MAX_ITERATIONS: usize = 50;
+const CONVERGENCE_THRESHOLD: f64 = 1e-6;
+const INITIAL_DAMPING: f64 = 1e-3;
+
+const MIN_RSSI_STD: f64 = 1.0;
+/// Ratio of the standard error of the median to the standard error of the mean.
+const MEDIAN_EFFICIENCY: f64 = 1.2533;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RssiFilter {
+    Median,
+    Kalman {
+        process_noise: f64,
+        measurement_noise: f64,
+    },
+}
+
+pub struct Multilateration {
+    pub y: f64,
+    pub x: f64,
+    pub residual: f64,
+    /// `None` if the geometry of the sensors does not determine the position in every
+    /// direction, e.g. if the sensors and the position are collinear.
+    pub covariance: Option<Matrix2<f64>>,
+    pub sensors: usize,
+    pub samples: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct ErrorEllipse {
+    pub semi_major: f64,
+    pub semi_minor: f64,
+    /// Angle of the semi-major axis in degrees, counterclockwise from the x axis.
+    pub orientation: f64,
+}
+
+impl ErrorEllipse {
+    /// 1-sigma ellipse of the given position covariance.
+    pub fn from_covariance(covariance: &Matrix2<f64>) -> Self {
+        let eigen = covariance.symmetric_eigen();
+
+        let (major, minor) = if eigen.eigenvalues[0] >= eigen.eigenvalues[1] {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+        let axis = eigen.eigenvectors.column(major);
+
+        Self {
+            semi_major: eigen.eigenvalues[major].max(0.0).sqrt(),
+            semi_minor: eigen.eigenvalues[minor].max(0.0).sqrt(),
+            orientation: axis[1].atan2(axis[0]).to_degrees().rem_euclid(180.0),
+        }
+    }
+
+    /// Points on the ellipse around the given center, counterclockwise from the end of the
+    /// semi-major axis.
+    pub fn outline(&self, x: f64, y: f64, points: usize) -> Vec<(f64, f64)> {
+        let (sin, cos) = self.orientation.to_radians().sin_cos();
+
+        (0..points)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * i as f64 / points as f64;
+                let (a, b) = (self.semi_major * angle.cos(), self.semi_minor * angle.sin());
+
+                (x + a * cos - b * sin, y + a * sin + b * cos)
+            })
+            .collect()
+    }
+}
+
+struct Range {
+    position: Vector2<f64>,
+    distance: f64,
+    std: f64,
+}
+
+fn rssi_to_distance(rssi: f64, rssi_at_1m: f64, path_loss_exponent: f64) -> f64 {
+    let exponent = (rssi_at_1m - rssi) / (10.0 * path_loss_exponent);
 
     10.0f64.powf(exponent)
 }
 
-pub async fn trilaterate(
-    s1: &SensorCandidate,
-    s2: &SensorCandidate,
-    s3: &SensorCandidate,
-) -> (f64, f64) {
-    debug!("s1: {} {} {}", s1.x, s1.y, s1.rssi);
-    debug!("s2: {} {} {}", s2.x, s2.y, s2.rssi);
-    debug!("s3: {} {} {}", s3.x, s3.y, s3.rssi);
+/// Propagates the uncertainty of the RSSI through the log-distance path loss model.
+fn distance_std(distance: f64, rssi_std: f64, path_loss_exponent: f64) -> f64 {
+    distance * std::f64::consts::LN_10 / (10.0 * path_loss_exponent) * rssi_std.max(MIN_RSSI_STD)
+}
+
+pub fn calculate_reference_rssi(rssi: f64, distance: f64, path_loss_exponent: f64) -> f64 {
+    rssi + 10.0 * path_loss_exponent * distance.log10()
+}
+
+pub fn trilaterate(candidates: &[SensorCandidate]) -> Option<Multilateration> {
+    if candidates.len() < MIN_TRILATERATION_SENSORS {
+        return None;
+    }
+
+    let ranges: Vec<Range> = candidates
+        .iter()
+        .map(|c| {
+            let distance = rssi_to_distance(c.rssi, c.rssi_at_1m, c.path_loss_exponent);
+            debug!("s: {} {} {} d: {}", c.x, c.y, c.rssi, distance);
+
+            Range {
+                position: Vector2::new(c.x, c.y),
+                distance,
+                std: distance_std(distance, c.rssi_std, c.path_loss_exponent),
+            }
+        })
+        .collect();
+
+    let initial = linear_estimate(&ranges).unwrap_or_else(|| centroid(&ranges));
+    let position = levenberg_marquardt(&ranges, initial);
+    let residual = rms_residual(&ranges, &position);
+
+    debug!("y: {} x: {} residual: {}", position.y, position.x, residual);
+
+    if !position.x.is_finite() || !position.y.is_finite() {
+        return None;
+    }
+
+    Some(Multilateration {
+        y: position.y,
+        x: position.x,
+        residual,
+        covariance: covariance(&ranges, &position),
+        sensors: candidates.len(),
+        samples: candidates.iter().map(|c| c.samples).sum(),
+    })
+}
+
+/// Solves the circle equations linearized against the first sensor in the least squares sense.
+fn linear_estimate(ranges: &[Range]) -> Option<Vector2<f64>> {
+    let r0 = &ranges[0];
+
+    let rows = ranges.len() - 1;
+    let mut a = DMatrix::zeros(rows, 2);
+    let mut b = DVector::zeros(rows);
+
+    for (i, r) in ranges[1..].iter().enumerate() {
+        a[(i, 0)] = 2.0 * (r.position.x - r0.position.x);
+        a[(i, 1)] = 2.0 * (r.position.y - r0.position.y);
+        b[i] = r0.distance.powi(2) - r.distance.powi(2) + r.position.norm_squared()
+            - r0.position.norm_squared();
+    }
+
+    let solution = a.svd(true, true).solve(&b, 1e-9).ok()?;
+    let estimate = Vector2::new(solution[0], solution[1]);
+
+    (estimate.x.is_finite() && estimate.y.is_finite()).then_some(estimate)
+}
+
+fn centroid(ranges: &[Range]) -> Vector2<f64> {
+    ranges.iter().map(|r| r.position).sum::<Vector2<f64>>() / ranges.len() as f64
+}
+
+/// Normal equations of the range residuals, each weighted by the inverse of its variance.
+fn normal_equations(ranges: &[Range], position: &Vector2<f64>) -> (Matrix2<f64>, Vector2<f64>) {
+    let mut jtj = Matrix2::zeros();
+    let mut jtr = Vector2::zeros();
+
+    for r in ranges {
+        let delta = position - r.position;
+        let range = delta.norm().max(f64::EPSILON);
+        let jacobian = delta / range / r.std;
+        let residual = (range - r.distance) / r.std;
 
-    let d1 = rssi_to_distance(s1.rssi as f64, s1.environment);
-    let d2 = rssi_to_distance(s2.rssi as f64, s2.environment);
-    let d3 = rssi_to_distance(s3.rssi as f64, s3.environment);
+        jtj += jacobian * jacobian.transpose();
+        jtr += jacobian * residual;
+    }
+
+    (jtj, jtr)
+}
 
-    debug!("d: {} {} {}", d1, d2, d3);
+fn levenberg_marquardt(ranges: &[Range], initial: Vector2<f64>) -> Vector2<f64> {
+    let mut position = initial;
+    let mut cost = chi_squared(ranges, &position);
+    let mut damping = INITIAL_DAMPING;
 
-    let a = 2.0 * (s2.x - s1.x);
-    let b = 2.0 * (s2.y - s1.y);
-    let c = d1.powi(2) - d2.powi(2) + s2.x.powi(2) - s1.x.powi(2) + s2.y.powi(2) - s1.y.powi(2);
+    for _ in 0..MAX_ITERATIONS {
+        let (jtj, jtr) = normal_equations(ranges, &position);
 
-    let d = 2.0 * (s3.x - s2.x);
-    let e = 2.0 * (s3.y - s2.y);
-    let f = d2.powi(2) - d3.powi(2) + s3.x.powi(2) - s2.x.powi(2) + s3.y.powi(2) - s2.y.powi(2);
+        let augmented = jtj + Matrix2::from_diagonal(&jtj.diagonal()) * damping;
+        let Some(step) = augmented.try_inverse().map(|inv| -(inv * jtr)) else {
+            break;
+        };
 
-    let denom = (a * e) - (b * d);
+        let candidate = position + step;
+        let candidate_cost = chi_squared(ranges, &candidate);
 
-    let x = (c * e - b * f) / denom;
-    let y = (a * f - c * d) / denom;
+        if candidate_cost < cost {
+            position = candidate;
+            cost = candidate_cost;
+            damping /= 10.0;
 
-    debug!("y: {} x: {}", y, x);
+            if step.norm() < CONVERGENCE_THRESHOLD {
+                break;
+            }
+        } else {
+            damping *= 10.0;
+        }
+    }
 
-    (y, x)
+    position
 }
 
-pub fn calculate_rssi_median(queue: &VecDeque<(i8, Instant)>) -> i8 {
+/// Covariance of the position estimate, inflated by the reduced chi-squared if the ranges are
+/// less consistent than their uncertainties suggest.
+fn covariance(ranges: &[Range], position: &Vector2<f64>) -> Option<Matrix2<f64>> {
+    let (jtj, _) = normal_equations(ranges, position);
+
+    let degrees_of_freedom = (ranges.len() - 2) as f64;
+    let reduced_chi_squared = chi_squared(ranges, position) / degrees_of_freedom;
+
+    jtj.try_inverse()
+        .map(|covariance| covariance * reduced_chi_squared.max(1.0))
+}
+
+fn chi_squared(ranges: &[Range], position: &Vector2<f64>) -> f64 {
+    ranges
+        .iter()
+        .map(|r| (((position - r.position).norm() - r.distance) / r.std).powi(2))
+        .sum()
+}
+
+fn rms_residual(ranges: &[Range], position: &Vector2<f64>) -> f64 {
+    let sum: f64 = ranges
+        .iter()
+        .map(|r| ((position - r.position).norm() - r.distance).powi(2))
+        .sum();
+
+    (sum / ranges.len() as f64).sqrt()
+}
+
+pub fn calculate_rssi_median(queue: &VecDeque<(i8, u64)>) -> i8 {
     let mut values: Vec<i8> = queue.iter().map(|(rssi, _)| *rssi).collect();
 
     values.sort_unstable();
 
     values[values.len() / 2]
 }
+
+pub fn calculate_rssi_median_std(queue: &VecDeque<(i8, u64)>) -> f64 {
+    let n = queue.len() as f64;
+    let mean = queue.iter().map(|(rssi, _)| *rssi as f64).sum::<f64>() / n;
+    let variance = queue
+        .iter()
+        .map(|(rssi, _)| (*rssi as f64 - mean).powi(2))
+        .sum::<f64>()
+        / (n - 1.0).max(1.0);
+
+    MEDIAN_EFFICIENCY * (variance / n).sqrt()
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    let mut values = values.to_vec();
+
+    values.sort_unstable_by(f64::total_cmp);
+
+    values[values.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSSI_AT_1M: f64 = -40.0;
+    const PATH_LOSS_EXPONENT: f64 = 2.0;
+
+    /// A sensor that measured the exact RSSI of a device at the given distance.
+    fn candidate(x: f64, y: f64, distance: f64) -> SensorCandidate {
+        SensorCandidate {
+            y,
+            x,
+            path_loss_exponent: PATH_LOSS_EXPONENT,
+            rssi_at_1m: RSSI_AT_1M,
+            rssi: calculate_rssi(distance),
+            rssi_std: 2.0,
+            samples: 10,
+        }
+    }
+
+    fn calculate_rssi(distance: f64) -> f64 {
+        RSSI_AT_1M - 10.0 * PATH_LOSS_EXPONENT * distance.log10()
+    }
+
+    fn candidates(sensors: &[(f64, f64)], device: Vector2<f64>) -> Vec<SensorCandidate> {
+        sensors
+            .iter()
+            .map(|&(x, y)| candidate(x, y, (Vector2::new(x, y) - device).norm()))
+            .collect()
+    }
+
+    #[test]
+    fn recovers_position_from_exact_ranges() {
+        let sensors = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 8.0),
+            (0.0, 8.0),
+            (5.0, -3.0),
+        ];
+        let device = Vector2::new(3.5, 6.0);
+
+        let result = trilaterate(&candidates(&sensors, device)).unwrap();
+
+        assert!((result.x - device.x).abs() < 1e-4, "x: {}", result.x);
+        assert!((result.y - device.y).abs() < 1e-4, "y: {}", result.y);
+        assert!(result.residual < 1e-4);
+        assert_eq!(result.sensors, sensors.len());
+        assert_eq!(result.samples, 10 * sensors.len());
+    }
+
+    #[test]
+    fn recovers_position_outside_of_the_sensors() {
+        let sensors = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)];
+        let device = Vector2::new(9.0, -2.5);
+
+        let result = trilaterate(&candidates(&sensors, device)).unwrap();
+
+        assert!((result.x - device.x).abs() < 1e-3, "x: {}", result.x);
+        assert!((result.y - device.y).abs() < 1e-3, "y: {}", result.y);
+    }
+
+    #[test]
+    fn converges_from_a_distant_initial_estimate() {
+        let sensors = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let device = Vector2::new(2.0, 7.0);
+
+        let ranges: Vec<Range> = sensors
+            .iter()
+            .map(|&(x, y)| {
+                let position = Vector2::new(x, y);
+
+                Range {
+                    position,
+                    distance: (position - device).norm(),
+                    std: 1.0,
+                }
+            })
+            .collect();
+
+        let position = levenberg_marquardt(&ranges, Vector2::new(30.0, -20.0));
+
+        assert!((position - device).norm() < 1e-4, "position: {}", position);
+    }
+
+    #[test]
+    fn keeps_positions_of_collinear_sensors_without_covariance() {
+        let sensors = [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)];
+        let device = Vector2::new(3.0, 0.0);
+
+        let result = trilaterate(&candidates(&sensors, device)).unwrap();
+
+        assert!((result.x - device.x).abs() < 1e-4, "x: {}", result.x);
+        assert!(result.y.abs() < 1e-4, "y: {}", result.y);
+        assert!(result.covariance.is_none());
+    }
+
+    #[test]
+    fn requires_three_sensors() {
+        let device = Vector2::new(1.0, 1.0);
+
+        assert!(trilaterate(&candidates(&[(0.0, 0.0), (4.0, 0.0)], device)).is_none());
+    }
+}
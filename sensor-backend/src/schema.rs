@@ -0,0 +1,19 @@
+//! OpenAPI schemas of the `sensor_lib` types in the API, which are defined here so that
+//! `sensor_lib` stays `no_std`.
+
+use utoipa::ToSchema;
+
+// Mirrors the serde representation of `sensor_lib::Environment`.
+/// Propagation environment of a sensor, which sets its path loss exponent unless it was fitted.
+/// `Custom` gives the exponent.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub enum Environment {
+    FreeSpace,
+    UrbanArea,
+    ShadowedUrban,
+    InBuildingLOS,
+    ObstructedInBuilding,
+    ObstructedInFactory,
+    Custom(f64),
+}
@@ -1,81 +1,528 @@
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use sensor_lib::Environment;
-use serde::Serialize;
+use nalgebra::{Matrix2, Vector2};
+use sensor_lib::probe::ProbeSignature;
+use sensor_lib::protocol::{FrameError, FrameHeader, SensorKey};
+use sensor_lib::{Environment, Measurement, SensorInfo};
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
-use tokio::time::Instant;
+use tokio::task::spawn_blocking;
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
 
-use crate::coords::transform_local_to_global;
-use crate::rssi::{calculate_rssi_median, trilaterate};
+use crate::auth::{RejectionStats, ReplayWindow};
+use crate::calibration::CalibrationSession;
+use crate::clustering::{DeviceClusters, PseudoDevice, is_randomized};
+use crate::config::{Config, PacketPositionPolicy, RssiConfig};
+use crate::coords::{Georeference, GeoreferenceError};
+use crate::events::{Event, EventBus, Subscription};
+use crate::kalman::KalmanFilter;
+use crate::path_loss::{
+    MAX_FIT_SAMPLES, PathLossFit, PathLossSample, ReferenceTransmitter, fit_path_loss,
+};
+use crate::registry::{ReportedPosition, SensorPatch, SensorRegistration, default_enabled};
+use crate::rssi::{
+    ErrorEllipse, MIN_TRILATERATION_SENSORS, RssiFilter, calculate_rssi_median,
+    calculate_rssi_median_std, trilaterate,
+};
+use crate::stats::PacketStats;
+use crate::storage::{Storage, StorageConfig, StorageWrite, StorageWriter};
+use crate::tracking::{MotionTracker, TrackerConfig};
 
-const MIN_MEASUREMENT_ENTRIES: usize = 10;
-const MAX_MEASUREMENT_AGE: Duration = Duration::from_secs(60);
+const MAX_TRACK_LENGTH: usize = 1000;
+const MAX_TRACK_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct Sensor {
     pub id: u8,
     pub x: f64,
     pub y: f64,
     pub lat: f64,
     pub lon: f64,
+    #[schema(value_type = crate::schema::Environment)]
     pub environment: Environment,
+    pub rssi_at_1m: f64,
+    pub rssi_offset: f64,
+    pub path_loss: Option<PathLossFit>,
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Measurements of disabled sensors are dropped.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub conflict: Option<ReportedPosition>,
+}
+
+impl Sensor {
+    /// The fitted path loss exponent if available, the exponent of the environment otherwise.
+    pub fn path_loss_exponent(&self) -> f64 {
+        self.path_loss
+            .as_ref()
+            .map(|fit| fit.exponent)
+            .filter(|exponent| *exponent > 0.0)
+            .unwrap_or_else(|| self.environment.as_f64())
+    }
+
+    pub fn has_global_position(&self) -> bool {
+        self.lat.is_finite() && self.lon.is_finite() && (self.lat != 0.0 || self.lon != 0.0)
+    }
 }
 
 pub struct SensorCandidate {
     pub y: f64,
     pub x: f64,
-    pub environment: Environment,
-    pub rssi: i8,
+    pub path_loss_exponent: f64,
+    pub rssi_at_1m: f64,
+    pub rssi: f64,
+    pub rssi_std: f64,
+    pub samples: usize,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 pub struct Trilateration {
+    /// ID of the pseudo-device, the fingerprint of its first address.
     pub fingerprint: u64,
+    /// Fingerprints of all addresses of the pseudo-device.
+    #[serde(default)]
+    pub members: Vec<u64>,
+    pub timestamp: u64,
     pub y: f64,
     pub x: f64,
-    pub lat: f64,
-    pub lon: f64,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub residual: f64,
+    /// `None` if the sensors do not determine the position in every direction.
+    pub covariance: Option<[[f64; 2]; 2]>,
+    pub error_ellipse: Option<ErrorEllipse>,
+    pub sensors: usize,
+    pub samples: usize,
+    pub tracked: TrackedPosition,
 }
 
-type MeasurementsMap = HashMap<u64, HashMap<u8, VecDeque<(i8, Instant)>>>;
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct TrackedPosition {
+    pub y: f64,
+    pub x: f64,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub vy: f64,
+    pub vx: f64,
+    pub speed: f64,
+    /// Direction of movement in degrees, counterclockwise from the x axis.
+    pub heading: f64,
+    pub error_ellipse: ErrorEllipse,
+}
+
+/// A pseudo-device with its current position.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Device {
+    pub fingerprint: u64,
+    pub members: Vec<u64>,
+    pub randomized: bool,
+    #[schema(value_type = Option<u64>)]
+    pub signature: Option<ProbeSignature>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub trilateration: Option<Trilateration>,
+}
+
+impl Device {
+    fn new(device: &PseudoDevice, trilateration: Option<Trilateration>) -> Self {
+        Self {
+            fingerprint: device.id,
+            members: device.members.clone(),
+            randomized: device.randomized,
+            signature: device.signature,
+            first_seen: device.first_seen,
+            last_seen: device.last_seen,
+            trilateration,
+        }
+    }
+
+    /// A device only known by its restored position.
+    fn from_trilateration(trilateration: &Trilateration) -> Self {
+        Self {
+            fingerprint: trilateration.fingerprint,
+            members: trilateration.members.clone(),
+            randomized: is_randomized(trilateration.fingerprint),
+            signature: None,
+            first_seen: trilateration.timestamp,
+            last_seen: trilateration.timestamp,
+            trilateration: Some(trilateration.clone()),
+        }
+    }
+}
+
+/// The measurements of a device that are waiting for the next position.
+#[derive(Serialize, ToSchema)]
+pub struct DeviceMeasurements {
+    pub fingerprint: u64,
+    /// Measurements a sensor needs before it is used for a position.
+    pub min_measurement_entries: usize,
+    pub sensors: Vec<SensorMeasurements>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SensorMeasurements {
+    pub sensor_id: u8,
+    /// Measurements of sensors with an unknown position are not used.
+    pub known: bool,
+    pub measurements: Vec<RawMeasurement>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RawMeasurement {
+    pub rssi: i8,
+    pub timestamp: u64,
+}
+
+struct SmoothedRssi {
+    rssi: f64,
+    std: f64,
+    samples: usize,
+}
+
+type MeasurementsMap = HashMap<u64, HashMap<u8, VecDeque<(i8, u64)>>>;
+type FiltersMap = HashMap<u64, HashMap<u8, KalmanFilter>>;
 
 pub struct SensorService {
+    packet_positions: PacketPositionPolicy,
+    rssi_filter: RssiFilter,
+    rssi_config: RssiConfig,
+    tracker_config: TrackerConfig,
+    storage: Option<Arc<dyn Storage>>,
+    storage_writer: Option<StorageWriter>,
+    storage_config: StorageConfig,
     sensors: RwLock<HashMap<u8, Sensor>>,
     measurements: RwLock<MeasurementsMap>,
+    filters: RwLock<FiltersMap>,
+    calibrations: RwLock<HashMap<u8, CalibrationSession>>,
+    references: RwLock<HashMap<u64, ReferenceTransmitter>>,
+    path_loss_samples: RwLock<HashMap<u8, VecDeque<PathLossSample>>>,
     trilaterations: RwLock<HashMap<u64, Trilateration>>,
+    tracks: RwLock<HashMap<u64, VecDeque<Trilateration>>>,
+    trackers: RwLock<HashMap<u64, MotionTracker>>,
+    packet_stats: RwLock<HashMap<u8, PacketStats>>,
+    clusters: RwLock<DeviceClusters>,
+    keys: HashMap<u8, SensorKey>,
+    replay_windows: RwLock<HashMap<u8, ReplayWindow>>,
+    rejections: RwLock<RejectionStats>,
+    events: EventBus,
 }
 
 impl SensorService {
-    pub fn new() -> Self {
-        Self {
-            sensors: RwLock::new(HashMap::new()),
+    pub fn new(config: &Config, storage: Option<Arc<dyn Storage>>) -> anyhow::Result<Self> {
+        let (sensors, trilaterations, replay_counters) = match &storage {
+            Some(storage) => (
+                storage.load_sensors()?,
+                storage.load_trilaterations()?,
+                storage.load_replay_counters()?,
+            ),
+            None => (Vec::new(), Vec::new(), Vec::new()),
+        };
+
+        info!(
+            "Restored {} sensors, {} trilaterations and {} replay counters",
+            sensors.len(),
+            trilaterations.len(),
+            replay_counters.len()
+        );
+
+        Ok(Self {
+            packet_positions: config.sensors.packet_positions,
+            rssi_filter: config.rssi.filter(),
+            rssi_config: config.rssi.clone(),
+            tracker_config: config.tracker,
+            storage_writer: storage.clone().map(StorageWriter::spawn).transpose()?,
+            storage,
+            storage_config: config.storage.clone(),
+            sensors: RwLock::new(sensors.into_iter().map(|s| (s.id, s)).collect()),
             measurements: RwLock::new(HashMap::new()),
-            trilaterations: RwLock::new(HashMap::new()),
+            filters: RwLock::new(HashMap::new()),
+            calibrations: RwLock::new(HashMap::new()),
+            references: RwLock::new(HashMap::new()),
+            path_loss_samples: RwLock::new(HashMap::new()),
+            trilaterations: RwLock::new(
+                trilaterations
+                    .into_iter()
+                    .map(|t| (t.fingerprint, t))
+                    .collect(),
+            ),
+            tracks: RwLock::new(HashMap::new()),
+            trackers: RwLock::new(HashMap::new()),
+            packet_stats: RwLock::new(HashMap::new()),
+            clusters: RwLock::new(DeviceClusters::default()),
+            keys: config
+                .auth
+                .keys
+                .iter()
+                .map(|k| (k.sensor_id, k.key))
+                .collect(),
+            replay_windows: RwLock::new(
+                replay_counters
+                    .into_iter()
+                    .map(|(id, counter)| (id, ReplayWindow::restore(counter)))
+                    .collect(),
+            ),
+            rejections: RwLock::new(RejectionStats::default()),
+            events: EventBus::default(),
+        })
+    }
+
+    /// Queues the write for the storage writer, it never waits for the disk.
+    fn persist(&self, write: impl FnOnce() -> StorageWrite) {
+        if let Some(writer) = &self.storage_writer {
+            writer.write(write());
+        }
+    }
+
+    /// Subscribes to the changes of the state after the given event ID, subscribe before
+    /// reading the state to miss none.
+    pub fn subscribe(&self, last_event_id: Option<&str>) -> Subscription {
+        self.events.subscribe(last_event_id)
+    }
+
+    pub fn event_id(&self, sequence: u64) -> String {
+        self.events.event_id(sequence)
+    }
+
+    fn publish(&self, event: Event) {
+        self.events.publish(event);
+    }
+
+    /// Removes the persisted measurements and positions that are older than their retention at
+    /// the given time.
+    pub async fn prune_storage(&self, now: u64) {
+        self.persist(|| StorageWrite::Prune {
+            measurements_before: now
+                .saturating_sub(self.storage_config.measurement_retention.as_millis() as u64),
+            trilaterations_before: now
+                .saturating_sub(self.storage_config.position_retention.as_millis() as u64),
+        });
+    }
+
+    fn new_sensor(&self, id: u8) -> Sensor {
+        Sensor {
+            id,
+            y: 0.0,
+            x: 0.0,
+            lat: 0.0,
+            lon: 0.0,
+            environment: Environment::FreeSpace,
+            rssi_at_1m: self.rssi_config.calibrated_rssi_at_1m,
+            rssi_offset: 0.0,
+            path_loss: None,
+            label: None,
+            enabled: true,
+            conflict: None,
+        }
+    }
+
+    /// Applies the position a sensor reported at the given time according to the packet
+    /// position policy.
+    pub async fn add_sensor(&self, info: &SensorInfo, timestamp: u64) {
+        let id = info.sensor_id;
+
+        let mut lock = self.sensors.write().await;
+
+        let reported = ReportedPosition {
+            y: info.y,
+            x: info.x,
+            lat: info.latitude,
+            lon: info.longitude,
+            environment: info.environment,
+            timestamp,
+        };
+
+        match (self.packet_positions, lock.get_mut(&id)) {
+            (PacketPositionPolicy::Accept, _) => {}
+            (_, None) => {
+                debug!("Ignored position of unregistered sensor {}", id);
+                return;
+            }
+            (PacketPositionPolicy::Ignore, Some(_)) => return,
+            (PacketPositionPolicy::Flag, Some(sensor)) => {
+                if reported.matches(sensor) && sensor.conflict.is_some() {
+                    sensor.conflict = None;
+                } else if reported.is_new_conflict(sensor) {
+                    warn!(
+                        "Sensor {} reports Y: {}, X: {}, Latitude: {}, Longitude: {}, which conflicts with its registration",
+                        id, reported.y, reported.x, reported.lat, reported.lon
+                    );
+                    sensor.conflict = Some(reported);
+                } else {
+                    return;
+                }
+
+                self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+                self.publish(Event::SensorUpdated(sensor.clone()));
+                return;
+            }
+        }
+
+        // The calibration and the settings are owned by the backend and must survive the
+        // position updates.
+        let mut sensor = lock
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| self.new_sensor(id));
+        sensor.y = reported.y;
+        sensor.x = reported.x;
+        sensor.lat = reported.lat;
+        sensor.lon = reported.lon;
+        sensor.environment = reported.environment;
+
+        if lock.get(&id) != Some(&sensor) {
+            self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+            self.publish(Event::SensorUpdated(sensor.clone()));
+        }
+
+        lock.insert(id, sensor);
+    }
+
+    pub async fn record_frame(&self, header: &FrameHeader, timestamp: u64) {
+        let mut lock = self.packet_stats.write().await;
+
+        lock.entry(header.sensor_id)
+            .and_modify(|stats| stats.record(header, timestamp))
+            .or_insert_with(|| PacketStats::new(header, timestamp));
+    }
+
+    pub fn sensor_key(&self, sensor_id: u8) -> Option<SensorKey> {
+        self.keys.get(&sensor_id).copied()
+    }
+
+    /// Rejects frames whose counter was already seen for the sensor, also before a restart.
+    pub async fn check_replay(&self, header: &FrameHeader, timestamp: u64) -> bool {
+        let counter = header.counter();
+        let mut lock = self.replay_windows.write().await;
+
+        let accepted = match lock.entry(header.sensor_id) {
+            Entry::Occupied(mut window) => window.get_mut().accept(counter),
+            Entry::Vacant(entry) => {
+                entry.insert(ReplayWindow::new(counter));
+                true
+            }
+        };
+
+        if accepted && lock[&header.sensor_id].highest() == counter {
+            self.persist(|| StorageWrite::SaveReplayCounter {
+                sensor_id: header.sensor_id,
+                counter,
+            });
         }
+
+        drop(lock);
+
+        if !accepted {
+            self.rejections.write().await.replayed += 1;
+
+            if let Some(stats) = self.packet_stats.write().await.get_mut(&header.sensor_id) {
+                stats.record_duplicate(timestamp);
+            }
+        }
+
+        accepted
+    }
+
+    pub async fn record_rejection(&self, error: &FrameError) {
+        self.rejections.write().await.record(error);
     }
 
-    pub async fn add_sensor(
+    pub async fn get_rejection_stats(&self) -> RejectionStats {
+        self.rejections.read().await.clone()
+    }
+
+    pub async fn get_packet_stats(&self) -> Vec<PacketStats> {
+        let lock = self.packet_stats.read().await;
+
+        lock.values().cloned().collect()
+    }
+
+    pub async fn get_georeference(&self) -> Result<Georeference, GeoreferenceError> {
+        let lock = self.sensors.read().await;
+
+        Georeference::fit(lock.values())
+    }
+
+    pub async fn set_calibration(
         &self,
         id: u8,
-        y: f64,
-        x: f64,
-        latitude: f64,
-        longitude: f64,
-        environment: Environment,
-    ) {
+        rssi_at_1m: f64,
+        rssi_offset: f64,
+    ) -> Option<Sensor> {
         let mut lock = self.sensors.write().await;
 
-        let sensor = Sensor {
-            id,
-            y,
-            x,
-            lat: latitude,
-            lon: longitude,
-            environment,
+        let sensor = lock.get_mut(&id)?;
+        sensor.rssi_at_1m = rssi_at_1m;
+        sensor.rssi_offset = rssi_offset;
+
+        self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+        self.publish(Event::SensorUpdated(sensor.clone()));
+
+        Some(sensor.clone())
+    }
+
+    pub async fn start_calibration(
+        &self,
+        id: u8,
+        fingerprint: u64,
+        distance: f64,
+        samples: usize,
+    ) -> Option<CalibrationSession> {
+        if !self.sensors.read().await.contains_key(&id) {
+            return None;
+        }
+
+        let session = CalibrationSession::new(id, fingerprint, distance, samples);
+
+        let mut lock = self.calibrations.write().await;
+        lock.insert(id, session.clone());
+
+        Some(session)
+    }
+
+    pub async fn get_calibration(&self, id: u8) -> Option<CalibrationSession> {
+        let lock = self.calibrations.read().await;
+
+        lock.get(&id).cloned()
+    }
+
+    pub async fn cancel_calibration(&self, id: u8) -> Option<CalibrationSession> {
+        let mut lock = self.calibrations.write().await;
+
+        lock.remove(&id)
+    }
+
+    async fn capture_calibration_sample(&self, fingerprint: u64, sensor_id: u8, rssi: i8) {
+        let mut c_lock = self.calibrations.write().await;
+
+        let Some(session) = c_lock
+            .get_mut(&sensor_id)
+            .filter(|session| session.fingerprint == fingerprint && !session.is_completed())
+        else {
+            return;
         };
 
-        lock.insert(id, sensor);
+        let mut s_lock = self.sensors.write().await;
+        let Some(sensor) = s_lock.get_mut(&sensor_id) else {
+            return;
+        };
+
+        if let Some(rssi_at_1m) = session.add_sample(
+            rssi as f64 + sensor.rssi_offset,
+            sensor.path_loss_exponent(),
+        ) {
+            info!("Calibrated sensor {}: RSSI at 1m {}", sensor_id, rssi_at_1m);
+
+            sensor.rssi_at_1m = rssi_at_1m;
+
+            self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+            self.publish(Event::SensorUpdated(sensor.clone()));
+        }
     }
 
     pub async fn get_sensors(&self) -> Vec<Sensor> {
@@ -84,76 +531,368 @@ impl SensorService {
         lock.values().cloned().collect()
     }
 
-    pub async fn add_measurement(&self, fingerprint: u64, sensor_id: u8, rssi: i8) {
+    pub async fn get_sensor(&self, id: u8) -> Option<Sensor> {
+        let lock = self.sensors.read().await;
+
+        lock.get(&id).cloned()
+    }
+
+    /// Registers a new sensor, `None` if the sensor already exists.
+    pub async fn create_sensor(&self, id: u8, registration: SensorRegistration) -> Option<Sensor> {
+        let mut lock = self.sensors.write().await;
+
+        if lock.contains_key(&id) {
+            return None;
+        }
+
+        let mut sensor = self.new_sensor(id);
+        registration.apply(&mut sensor);
+
+        self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+        self.publish(Event::SensorUpdated(sensor.clone()));
+
+        lock.insert(id, sensor.clone());
+
+        Some(sensor)
+    }
+
+    /// Registers a sensor or replaces the registration of an existing one, which keeps its
+    /// calibration. Returns the sensor and whether it was created.
+    pub async fn replace_sensor(&self, id: u8, registration: SensorRegistration) -> (Sensor, bool) {
+        let mut lock = self.sensors.write().await;
+
+        let created = !lock.contains_key(&id);
+        let sensor = lock.entry(id).or_insert_with(|| self.new_sensor(id));
+        registration.apply(sensor);
+
+        self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+        self.publish(Event::SensorUpdated(sensor.clone()));
+
+        (sensor.clone(), created)
+    }
+
+    pub async fn update_sensor(&self, id: u8, patch: SensorPatch) -> Option<Sensor> {
+        let mut lock = self.sensors.write().await;
+
+        let sensor = lock.get_mut(&id)?;
+        patch.apply(sensor);
+
+        self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+        self.publish(Event::SensorUpdated(sensor.clone()));
+
+        Some(sensor.clone())
+    }
+
+    pub async fn remove_sensor(&self, id: u8) -> Option<Sensor> {
+        let mut lock = self.sensors.write().await;
+
+        let sensor = lock.remove(&id)?;
+        drop(lock);
+
+        // A sensor registered again under the same ID starts without any state of this one. The
+        // replay window belongs to the key, which stays configured, so that the frames captured
+        // before stay rejected.
+        self.calibrations.write().await.remove(&id);
+        self.path_loss_samples.write().await.remove(&id);
+        self.packet_stats.write().await.remove(&id);
+
+        let mut m_lock = self.measurements.write().await;
+        for sensors in m_lock.values_mut() {
+            sensors.remove(&id);
+        }
+        m_lock.retain(|_, sensors| !sensors.is_empty());
+        drop(m_lock);
+
+        let mut f_lock = self.filters.write().await;
+        for filters in f_lock.values_mut() {
+            filters.remove(&id);
+        }
+        f_lock.retain(|_, filters| !filters.is_empty());
+        drop(f_lock);
+
+        self.persist(|| StorageWrite::DeleteSensor(id));
+        self.publish(Event::SensorRemoved { id });
+
+        Some(sensor)
+    }
+
+    pub async fn add_reference(&self, reference: ReferenceTransmitter) {
+        let mut lock = self.references.write().await;
+
+        lock.insert(reference.fingerprint, reference);
+    }
+
+    pub async fn get_references(&self) -> Vec<ReferenceTransmitter> {
+        let lock = self.references.read().await;
+
+        lock.values().cloned().collect()
+    }
+
+    pub async fn remove_reference(&self, fingerprint: u64) -> Option<ReferenceTransmitter> {
+        let mut lock = self.references.write().await;
+
+        lock.remove(&fingerprint)
+    }
+
+    async fn capture_path_loss_sample(&self, fingerprint: u64, sensor_id: u8, rssi: i8) {
+        let r_lock = self.references.read().await;
+        let Some(reference) = r_lock.get(&fingerprint).cloned() else {
+            return;
+        };
+        drop(r_lock);
+
+        let mut s_lock = self.sensors.write().await;
+
+        let (x, y) = match reference.sensor_id {
+            Some(id) if id == sensor_id => return,
+            Some(id) => match s_lock.get(&id) {
+                Some(sensor) => (sensor.x, sensor.y),
+                None => return,
+            },
+            None => (reference.x, reference.y),
+        };
+
+        let Some(sensor) = s_lock.get_mut(&sensor_id) else {
+            return;
+        };
+
+        let distance = (sensor.x - x).hypot(sensor.y - y);
+        let Some(sample) = PathLossSample::new(distance, rssi as f64 + sensor.rssi_offset) else {
+            return;
+        };
+
+        let mut p_lock = self.path_loss_samples.write().await;
+        let samples = p_lock.entry(sensor_id).or_default();
+
+        samples.push_back(sample);
+        if samples.len() > MAX_FIT_SAMPLES {
+            samples.pop_front();
+        }
+
+        let Some(fit) = fit_path_loss(samples, sensor.rssi_at_1m) else {
+            return;
+        };
+
+        if sensor
+            .path_loss
+            .as_ref()
+            .is_none_or(|published| fit.differs_from(published))
+        {
+            sensor.path_loss = Some(fit);
+
+            self.persist(|| StorageWrite::SaveSensor(sensor.clone()));
+            self.publish(Event::SensorUpdated(sensor.clone()));
+        }
+    }
+
+    /// Adds a measurement received at the given time in milliseconds since the unix epoch.
+    pub async fn add_measurement(&self, sensor_id: u8, measurement: &Measurement, timestamp: u64) {
+        let rssi = measurement.rssi;
+
+        if let Some(sensor) = self.sensors.read().await.get(&sensor_id)
+            && !sensor.enabled
+        {
+            return;
+        }
+
+        // Calibration and reference transmitters are identified by their own address.
+        self.capture_calibration_sample(measurement.fingerprint, sensor_id, rssi)
+            .await;
+        self.capture_path_loss_sample(measurement.fingerprint, sensor_id, rssi)
+            .await;
+
+        if self.storage_config.store_measurements {
+            self.persist(|| StorageWrite::SaveMeasurement {
+                fingerprint: measurement.fingerprint,
+                sensor_id,
+                rssi,
+                timestamp,
+            });
+        }
+
+        let (fingerprint, members) = {
+            let mut d_lock = self.clusters.write().await;
+            let device = d_lock.observe(sensor_id, measurement, timestamp);
+
+            (device.id, device.members.clone())
+        };
+
         let mut lock = self.measurements.write().await;
 
-        let now = Instant::now();
+        let max_age = self.rssi_config.max_measurement_age.as_millis() as u64;
 
-        let sensors = lock.entry(fingerprint.clone()).or_default();
+        let sensors = lock.entry(fingerprint).or_default();
 
         let queue = sensors.entry(sensor_id).or_default();
-        queue.push_back((rssi, now));
+        queue.push_back((rssi, timestamp));
 
-        if sensors.len() == 3 && sensors.values().all(|q| q.len() >= MIN_MEASUREMENT_ENTRIES) {
-            let s_lock = self.sensors.read().await;
-
-            let candidates: Option<Vec<SensorCandidate>> = sensors
+        let mut ready: Vec<(u8, SmoothedRssi)> = match self.rssi_filter {
+            RssiFilter::Median => sensors
                 .iter()
+                .filter(|(_, q)| q.len() >= self.rssi_config.min_measurement_entries)
                 .map(|(id, queue)| {
-                    let rssi = calculate_rssi_median(queue);
+                    let smoothed = SmoothedRssi {
+                        rssi: calculate_rssi_median(queue) as f64,
+                        std: calculate_rssi_median_std(queue),
+                        samples: queue.len(),
+                    };
+
+                    (*id, smoothed)
+                })
+                .collect(),
+            RssiFilter::Kalman {
+                process_noise,
+                measurement_noise,
+            } => {
+                if queue.len() > self.rssi_config.min_measurement_entries {
+                    queue.pop_front();
+                }
+
+                let mut f_lock = self.filters.write().await;
+                let filters = f_lock.entry(fingerprint).or_default();
+
+                filters
+                    .entry(sensor_id)
+                    .and_modify(|filter| {
+                        filter.update(rssi as f64, timestamp);
+                    })
+                    .or_insert_with(|| {
+                        KalmanFilter::new(process_noise, measurement_noise, rssi as f64, timestamp)
+                    });
+
+                filters
+                    .iter()
+                    .filter(|(_, filter)| timestamp.saturating_sub(filter.last_update()) <= max_age)
+                    .map(|(id, filter)| {
+                        let smoothed = SmoothedRssi {
+                            rssi: filter.estimate(),
+                            std: filter.std(),
+                            samples: filter.updates(),
+                        };
 
-                    s_lock.get(id).map(|sensor| SensorCandidate {
-                        y: sensor.y,
-                        x: sensor.x,
-                        environment: sensor.environment,
-                        rssi,
+                        (*id, smoothed)
                     })
+                    .collect()
+            }
+        };
+
+        // A fixed order of the sensors keeps the solution reproducible.
+        ready.sort_by_key(|(id, _)| *id);
+
+        if ready.len() >= MIN_TRILATERATION_SENSORS {
+            let s_lock = self.sensors.read().await;
+
+            let candidates: Vec<SensorCandidate> = ready
+                .iter()
+                .filter_map(|(id, smoothed)| {
+                    s_lock
+                        .get(id)
+                        .filter(|sensor| sensor.enabled)
+                        .map(|sensor| SensorCandidate {
+                            y: sensor.y,
+                            x: sensor.x,
+                            path_loss_exponent: sensor.path_loss_exponent(),
+                            rssi_at_1m: sensor.rssi_at_1m,
+                            rssi: smoothed.rssi + sensor.rssi_offset,
+                            rssi_std: smoothed.std,
+                            samples: smoothed.samples,
+                        })
                 })
                 .collect();
 
             drop(s_lock);
 
-            if let Some(candidates) = candidates {
-                let (y, x) = trilaterate(&candidates[0], &candidates[1], &candidates[2]).await;
+            if let Some(result) = trilaterate(&candidates) {
+                let (y, x) = (result.y, result.x);
+
+                let georeference = self
+                    .get_georeference()
+                    .await
+                    .inspect_err(|e| debug!("Failed to georeference trilateration: {}", e))
+                    .ok();
+                let to_global = |x: f64, y: f64| {
+                    georeference
+                        .as_ref()
+                        .map(|georeference| georeference.local_to_global(x, y))
+                        .unzip()
+                };
 
-                let s_lock = self.sensors.read().await;
-                let sensors: Vec<&Sensor> = s_lock.values().collect();
+                let (lat, lon) = to_global(x, y);
 
-                let (lat, lon) =
-                    transform_local_to_global(x, y, sensors[0], sensors[1], sensors[2])
-                        .unwrap_or((0.0, 0.0));
+                let tracker = self
+                    .update_tracker(
+                        fingerprint,
+                        Vector2::new(x, y),
+                        result.covariance.as_ref(),
+                        timestamp,
+                    )
+                    .await;
+                let tracked_position = tracker.position();
+                let velocity = tracker.velocity();
+                let (tracked_lat, tracked_lon) = to_global(tracked_position.x, tracked_position.y);
 
-                drop(s_lock);
+                let tracked = TrackedPosition {
+                    y: tracked_position.y,
+                    x: tracked_position.x,
+                    lat: tracked_lat,
+                    lon: tracked_lon,
+                    vy: velocity.y,
+                    vx: velocity.x,
+                    speed: velocity.norm(),
+                    heading: tracker.heading(),
+                    error_ellipse: ErrorEllipse::from_covariance(&tracker.position_covariance()),
+                };
 
                 let mut t_lock = self.trilaterations.write().await;
 
                 let trilateration = Trilateration {
                     fingerprint,
+                    members,
+                    timestamp,
                     y,
                     x,
                     lat,
                     lon,
+                    residual: result.residual,
+                    covariance: result.covariance.map(Into::into),
+                    error_ellipse: result
+                        .covariance
+                        .as_ref()
+                        .map(ErrorEllipse::from_covariance),
+                    sensors: result.sensors,
+                    samples: result.samples,
+                    tracked,
                 };
 
-                t_lock.insert(fingerprint, trilateration);
+                t_lock.insert(fingerprint, trilateration.clone());
                 drop(t_lock);
 
-                lock.remove(&fingerprint);
+                self.persist(|| StorageWrite::SaveTrilateration(trilateration.clone()));
+                self.publish(Event::PositionUpdated(trilateration.clone()));
+
+                self.add_track_entry(trilateration).await;
+
+                // The Kalman filter keeps its state to update the position continuously, the
+                // median needs a fresh batch of measurements instead.
+                if let RssiFilter::Median = self.rssi_filter {
+                    lock.remove(&fingerprint);
+                }
             }
         }
 
         lock.retain(|_, sensors| {
-            if let Some(timestamp) = sensors
+            sensors
                 .values()
                 .filter_map(|queue| queue.back().map(|(_, timestamp)| *timestamp))
                 .max()
-            {
-                if timestamp < now.checked_sub(MAX_MEASUREMENT_AGE).unwrap_or(now) {
-                    return false;
-                }
-            }
-            return true;
+                .is_none_or(|last| last >= timestamp.saturating_sub(max_age))
         });
+
+        if let RssiFilter::Kalman { .. } = self.rssi_filter {
+            let mut f_lock = self.filters.write().await;
+
+            f_lock.retain(|fingerprint, _| lock.contains_key(fingerprint));
+        }
     }
 
     pub async fn get_trilaterations(&self) -> Vec<Trilateration> {
@@ -161,4 +900,206 @@ impl SensorService {
 
         lock.values().cloned().collect()
     }
+
+    pub async fn get_devices(&self) -> Vec<Device> {
+        let d_lock = self.clusters.read().await;
+        let t_lock = self.trilaterations.read().await;
+
+        let mut devices: Vec<Device> = d_lock
+            .devices()
+            .map(|device| Device::new(device, t_lock.get(&device.id).cloned()))
+            .collect();
+
+        devices.extend(
+            t_lock
+                .values()
+                .filter(|t| d_lock.get(t.fingerprint).is_none())
+                .map(Device::from_trilateration),
+        );
+
+        devices
+    }
+
+    /// Looks up a device by its ID or the fingerprint of any of its addresses.
+    pub async fn get_device(&self, fingerprint: u64) -> Option<Device> {
+        let d_lock = self.clusters.read().await;
+        let t_lock = self.trilaterations.read().await;
+
+        let id = d_lock.device_of(fingerprint).unwrap_or(fingerprint);
+
+        match d_lock.get(id) {
+            Some(device) => Some(Device::new(device, t_lock.get(&id).cloned())),
+            None => t_lock.get(&id).map(Device::from_trilateration),
+        }
+    }
+
+    pub async fn get_device_measurements(&self, fingerprint: u64) -> Option<DeviceMeasurements> {
+        let device = self.get_device(fingerprint).await?;
+
+        let lock = self.measurements.read().await;
+        let s_lock = self.sensors.read().await;
+
+        let mut sensors: Vec<SensorMeasurements> = lock
+            .get(&device.fingerprint)
+            .into_iter()
+            .flatten()
+            .map(|(id, queue)| SensorMeasurements {
+                sensor_id: *id,
+                known: s_lock.contains_key(id),
+                measurements: queue
+                    .iter()
+                    .map(|(rssi, timestamp)| RawMeasurement {
+                        rssi: *rssi,
+                        timestamp: *timestamp,
+                    })
+                    .collect(),
+            })
+            .collect();
+        sensors.sort_by_key(|s| s.sensor_id);
+
+        Some(DeviceMeasurements {
+            fingerprint: device.fingerprint,
+            min_measurement_entries: self.rssi_config.min_measurement_entries,
+            sensors,
+        })
+    }
+
+    /// Removes the positions of devices that were not located since the expiry time.
+    pub async fn expire_devices(&self, now: u64) {
+        let oldest = now.saturating_sub(self.tracker_config.device_expiry.as_millis() as u64);
+
+        let mut lock = self.trilaterations.write().await;
+
+        let expired: Vec<u64> = lock
+            .values()
+            .filter(|t| t.timestamp < oldest)
+            .map(|t| t.fingerprint)
+            .collect();
+
+        for fingerprint in expired {
+            lock.remove(&fingerprint);
+
+            debug!("Device {} expired", fingerprint);
+            self.publish(Event::DeviceExpired { fingerprint });
+        }
+    }
+
+    async fn update_tracker(
+        &self,
+        fingerprint: u64,
+        position: Vector2<f64>,
+        covariance: Option<&Matrix2<f64>>,
+        timestamp: u64,
+    ) -> MotionTracker {
+        let mut lock = self.trackers.write().await;
+
+        let tracker = lock
+            .entry(fingerprint)
+            .and_modify(|tracker| {
+                tracker.update(&self.tracker_config, position, covariance, timestamp)
+            })
+            .or_insert_with(|| MotionTracker::new(position, covariance, timestamp))
+            .clone();
+
+        let oldest = timestamp.saturating_sub(self.tracker_config.max_gap.as_millis() as u64);
+        lock.retain(|_, tracker| tracker.timestamp() >= oldest);
+
+        tracker
+    }
+
+    async fn add_track_entry(&self, trilateration: Trilateration) {
+        let mut lock = self.tracks.write().await;
+
+        let oldest = trilateration
+            .timestamp
+            .saturating_sub(MAX_TRACK_AGE.as_millis() as u64);
+
+        let track = lock.entry(trilateration.fingerprint).or_default();
+        track.push_back(trilateration);
+
+        if track.len() > MAX_TRACK_LENGTH {
+            track.pop_front();
+        }
+
+        lock.retain(|_, track| {
+            while track.front().is_some_and(|t| t.timestamp < oldest) {
+                track.pop_front();
+            }
+
+            !track.is_empty()
+        });
+    }
+
+    /// The positions of a device, looked up by its ID or the fingerprint of any of its addresses,
+    /// together with the device ID. Addresses that were located as a device of their own before
+    /// they joined the device contribute their positions too.
+    pub async fn get_track(
+        &self,
+        fingerprint: u64,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> Option<(u64, Vec<Trilateration>)> {
+        let d_lock = self.clusters.read().await;
+
+        let id = d_lock.device_of(fingerprint).unwrap_or(fingerprint);
+        let mut ids = vec![id];
+        if let Some(device) = d_lock.get(id) {
+            ids.extend(device.members.iter().filter(|member| **member != id));
+        }
+
+        drop(d_lock);
+
+        let mut track = Vec::new();
+        let mut found = false;
+
+        for id in ids {
+            if let Some(positions) = self.get_fingerprint_track(id, since, until).await {
+                track.extend(positions);
+                found = true;
+            }
+        }
+
+        track.sort_by_key(|t| t.timestamp);
+
+        found.then_some((id, track))
+    }
+
+    async fn get_fingerprint_track(
+        &self,
+        fingerprint: u64,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> Option<Vec<Trilateration>> {
+        if let Some(storage) = self.storage.clone() {
+            let track = spawn_blocking(move || storage.load_track(fingerprint, since, until)).await;
+
+            match track {
+                Ok(Ok(track)) if !track.is_empty() => return Some(track),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Failed to load track: {}", e),
+                Err(e) => error!("Failed to load track: {}", e),
+            }
+        }
+
+        let lock = self.tracks.read().await;
+
+        let track = lock.get(&fingerprint)?;
+
+        Some(
+            track
+                .iter()
+                .filter(|t| since.is_none_or(|since| t.timestamp >= since))
+                .filter(|t| until.is_none_or(|until| t.timestamp <= until))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Milliseconds since the unix epoch.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
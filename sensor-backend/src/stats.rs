@@ -0,0 +1,144 @@
+use sensor_lib::protocol::FrameHeader;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+const REORDER_WINDOW_MS: u64 = 10_000;
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct PacketStats {
+    pub sensor_id: u8,
+    pub received: u64,
+    /// Frames missing in the sequence, assuming the node sends every sequence number once.
+    pub lost: u64,
+    pub loss_rate: f64,
+    /// Frames rejected by the replay protection, counted when the frame is checked.
+    pub duplicates: u64,
+    pub out_of_order: u64,
+    pub restarts: u64,
+    pub last_sequence: u32,
+    pub last_uptime_ms: u64,
+    /// Time of the last frame in milliseconds since the unix epoch.
+    pub last_seen: u64,
+}
+
+impl PacketStats {
+    pub fn new(header: &FrameHeader, timestamp: u64) -> Self {
+        Self {
+            sensor_id: header.sensor_id,
+            received: 1,
+            lost: 0,
+            loss_rate: 0.0,
+            duplicates: 0,
+            out_of_order: 0,
+            restarts: 0,
+            last_sequence: header.sequence,
+            last_uptime_ms: header.uptime_ms,
+            last_seen: timestamp,
+        }
+    }
+
+    pub fn record(&mut self, header: &FrameHeader, timestamp: u64) {
+        self.received += 1;
+        self.last_seen = self.last_seen.max(timestamp);
+
+        // The sequence starts over if the node reboots, which shows in its uptime. Late frames
+        // are older too, but only by the time they were delayed in the network.
+        if header.uptime_ms + REORDER_WINDOW_MS < self.last_uptime_ms {
+            self.restarts += 1;
+            self.last_sequence = header.sequence;
+            self.last_uptime_ms = header.uptime_ms;
+        } else if header.sequence > self.last_sequence {
+            self.lost += (header.sequence - self.last_sequence - 1) as u64;
+            self.last_sequence = header.sequence;
+            self.last_uptime_ms = header.uptime_ms;
+        } else if header.sequence < self.last_sequence {
+            // A late frame was already counted as lost when the gap was detected.
+            self.out_of_order += 1;
+            self.lost = self.lost.saturating_sub(1);
+        }
+
+        self.loss_rate = self.lost as f64 / (self.received + self.lost) as f64;
+    }
+
+    pub fn record_duplicate(&mut self, timestamp: u64) {
+        self.duplicates += 1;
+        self.last_seen = self.last_seen.max(timestamp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(sequence: u32, uptime_ms: u64) -> FrameHeader {
+        FrameHeader {
+            sensor_id: 1,
+            epoch: 1,
+            sequence,
+            uptime_ms,
+        }
+    }
+
+    fn stats(frames: &[(u32, u64)]) -> PacketStats {
+        let (sequence, uptime_ms) = frames[0];
+        let mut stats = PacketStats::new(&header(sequence, uptime_ms), 0);
+
+        for (i, &(sequence, uptime_ms)) in frames.iter().enumerate().skip(1) {
+            stats.record(&header(sequence, uptime_ms), i as u64);
+        }
+
+        stats
+    }
+
+    #[test]
+    fn counts_a_continuous_sequence_without_loss() {
+        let stats = stats(&[(1, 100), (2, 200), (3, 300)]);
+
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(stats.loss_rate, 0.0);
+        assert_eq!(stats.last_sequence, 3);
+        assert_eq!(stats.last_seen, 2);
+    }
+
+    #[test]
+    fn counts_gaps_as_lost() {
+        let stats = stats(&[(1, 100), (2, 200), (6, 600)]);
+
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.lost, 3);
+        assert_eq!(stats.loss_rate, 0.5);
+    }
+
+    #[test]
+    fn recovers_late_frames_from_the_lost() {
+        let stats = stats(&[(1, 100), (4, 400), (2, 200), (5, 500)]);
+
+        assert_eq!(stats.received, 4);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.out_of_order, 1);
+        assert_eq!(stats.last_sequence, 5);
+    }
+
+    #[test]
+    fn restarts_the_sequence_after_a_reboot() {
+        let stats = stats(&[(100, 60_000), (101, 61_000), (1, 500), (2, 1_500)]);
+
+        assert_eq!(stats.restarts, 1);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(stats.out_of_order, 0);
+        assert_eq!(stats.last_sequence, 2);
+        assert_eq!(stats.last_uptime_ms, 1_500);
+    }
+
+    #[test]
+    fn counts_duplicates_separately_from_received_frames() {
+        let mut stats = stats(&[(1, 100), (2, 200)]);
+
+        stats.record_duplicate(10);
+
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.received, 2);
+        assert_eq!(stats.last_seen, 10);
+    }
+}
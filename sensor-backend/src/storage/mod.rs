@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::config::seconds;
+use crate::sensors::{Sensor, Trilateration};
+
+mod sqlite;
+
+/// Number of writes queued for the storage writer before writing waits for the disk.
+const STORAGE_QUEUE_CAPACITY: usize = 10_000;
+
+pub use sqlite::SqliteStorage;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Persistence is disabled without a database.
+    #[schema(value_type = Option<String>)]
+    pub database_path: Option<PathBuf>,
+    pub store_measurements: bool,
+    #[serde(with = "seconds")]
+    #[schema(
+        value_type = f64,
+        default = json!(StorageConfig::default().measurement_retention.as_secs_f64())
+    )]
+    pub measurement_retention: Duration,
+    #[serde(with = "seconds")]
+    #[schema(
+        value_type = f64,
+        default = json!(StorageConfig::default().position_retention.as_secs_f64())
+    )]
+    pub position_retention: Duration,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            database_path: None,
+            store_measurements: false,
+            measurement_retention: Duration::from_secs(24 * 60 * 60),
+            position_retention: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+pub trait Storage: Send + Sync {
+    fn load_sensors(&self) -> anyhow::Result<Vec<Sensor>>;
+
+    fn save_sensor(&self, sensor: &Sensor) -> anyhow::Result<()>;
+
+    /// Removes the sensor, its replay counter is kept with the key of the sensor.
+    fn delete_sensor(&self, id: u8) -> anyhow::Result<()>;
+
+    /// The highest accepted frame counter of every sensor.
+    fn load_replay_counters(&self) -> anyhow::Result<Vec<(u8, u64)>>;
+
+    /// Keeps the stored counter if it is higher, writes may be reordered with the frames.
+    fn save_replay_counter(&self, sensor_id: u8, counter: u64) -> anyhow::Result<()>;
+
+    fn save_measurement(
+        &self,
+        fingerprint: u64,
+        sensor_id: u8,
+        rssi: i8,
+        timestamp: u64,
+    ) -> anyhow::Result<()>;
+
+    fn save_trilateration(&self, trilateration: &Trilateration) -> anyhow::Result<()>;
+
+    /// The most recent trilateration of every fingerprint.
+    fn load_trilaterations(&self) -> anyhow::Result<Vec<Trilateration>>;
+
+    fn load_track(
+        &self,
+        fingerprint: u64,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> anyhow::Result<Vec<Trilateration>>;
+
+    /// Removes all measurements and trilaterations older than the given timestamps.
+    fn prune(&self, measurements_before: u64, trilaterations_before: u64) -> anyhow::Result<()>;
+}
+
+/// A change of the persisted state.
+pub enum StorageWrite {
+    SaveSensor(Sensor),
+    DeleteSensor(u8),
+    SaveReplayCounter {
+        sensor_id: u8,
+        counter: u64,
+    },
+    SaveMeasurement {
+        fingerprint: u64,
+        sensor_id: u8,
+        rssi: i8,
+        timestamp: u64,
+    },
+    SaveTrilateration(Trilateration),
+    Prune {
+        measurements_before: u64,
+        trilaterations_before: u64,
+    },
+}
+
+impl StorageWrite {
+    fn apply(self, storage: &dyn Storage) -> anyhow::Result<()> {
+        match self {
+            StorageWrite::SaveSensor(sensor) => storage.save_sensor(&sensor),
+            StorageWrite::DeleteSensor(id) => storage.delete_sensor(id),
+            StorageWrite::SaveReplayCounter { sensor_id, counter } => {
+                storage.save_replay_counter(sensor_id, counter)
+            }
+            StorageWrite::SaveMeasurement {
+                fingerprint,
+                sensor_id,
+                rssi,
+                timestamp,
+            } => storage.save_measurement(fingerprint, sensor_id, rssi, timestamp),
+            StorageWrite::SaveTrilateration(trilateration) => {
+                storage.save_trilateration(&trilateration)
+            }
+            StorageWrite::Prune {
+                measurements_before,
+                trilaterations_before,
+            } => storage.prune(measurements_before, trilaterations_before),
+        }
+    }
+}
+
+/// Executes the writes in order on a dedicated thread, so that a slow disk neither blocks the
+/// async runtime nor a caller that holds a lock. Only once the queue is full, writing waits for
+/// the disk instead of growing the queue without limit.
+pub struct StorageWriter {
+    sender: Option<SyncSender<StorageWrite>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl StorageWriter {
+    pub fn spawn(storage: Arc<dyn Storage>) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<StorageWrite>(STORAGE_QUEUE_CAPACITY);
+
+        let writer = thread::Builder::new()
+            .name("storage-writer".to_string())
+            .spawn(move || {
+                for write in receiver {
+                    if let Err(e) = write.apply(storage.as_ref()) {
+                        error!("Failed to persist state: {}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn write(&self, write: StorageWrite) {
+        if let Some(sender) = &self.sender
+            && sender.send(write).is_err()
+        {
+            error!("Failed to persist state: storage writer stopped");
+        }
+    }
+}
+
+/// Waits until all queued writes are persisted.
+impl Drop for StorageWriter {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        if let Some(writer) = self.writer.take()
+            && writer.join().is_err()
+        {
+            error!("Storage writer thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flushes_queued_writes_when_dropped() {
+        let storage = Arc::new(SqliteStorage::open(":memory:").unwrap());
+        let writer = StorageWriter::spawn(storage.clone()).unwrap();
+
+        for counter in 1..=1_000 {
+            writer.write(StorageWrite::SaveReplayCounter {
+                sensor_id: 1,
+                counter,
+            });
+        }
+        drop(writer);
+
+        assert_eq!(storage.load_replay_counters().unwrap(), vec![(1, 1_000)]);
+    }
+}
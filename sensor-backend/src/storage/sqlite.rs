@@ -0,0 +1,374 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, params};
+
+use crate::sensors::{Sensor, Trilateration};
+use crate::storage::Storage;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+
+    CREATE TABLE IF NOT EXISTS sensors (
+        id INTEGER PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS measurements (
+        fingerprint INTEGER NOT NULL,
+        sensor_id INTEGER NOT NULL,
+        rssi INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS measurements_timestamp ON measurements (timestamp);
+
+    CREATE TABLE IF NOT EXISTS trilaterations (
+        fingerprint INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS trilaterations_fingerprint_timestamp
+        ON trilaterations (fingerprint, timestamp);
+    CREATE INDEX IF NOT EXISTS trilaterations_timestamp ON trilaterations (timestamp);
+
+    CREATE TABLE IF NOT EXISTS replay_counters (
+        sensor_id INTEGER PRIMARY KEY,
+        counter INTEGER NOT NULL
+    );
+";
+
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> anyhow::Result<std::sync::MutexGuard<'_, Connection>> {
+        self.connection
+            .lock()
+            .map_err(|_| anyhow::anyhow!("SQLite connection poisoned"))
+    }
+}
+
+// SQLite integers are signed, so the fingerprints and timestamps are stored as their bit pattern.
+fn to_sql(value: u64) -> i64 {
+    value as i64
+}
+
+impl Storage for SqliteStorage {
+    fn load_sensors(&self) -> anyhow::Result<Vec<Sensor>> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare("SELECT data FROM sensors ORDER BY id")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+    }
+
+    fn save_sensor(&self, sensor: &Sensor) -> anyhow::Result<()> {
+        let connection = self.connection()?;
+
+        connection.execute(
+            "INSERT INTO sensors (id, data) VALUES (?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+            params![sensor.id, serde_json::to_string(sensor)?],
+        )?;
+
+        Ok(())
+    }
+
+    fn delete_sensor(&self, id: u8) -> anyhow::Result<()> {
+        let connection = self.connection()?;
+
+        connection.execute("DELETE FROM sensors WHERE id = ?1", params![id])?;
+
+        Ok(())
+    }
+
+    fn load_replay_counters(&self) -> anyhow::Result<Vec<(u8, u64)>> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare("SELECT sensor_id, counter FROM replay_counters")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, u8>(0)?, row.get::<_, i64>(1)? as u64))
+        })?;
+
+        rows.map(|row| Ok(row?)).collect()
+    }
+
+    fn save_replay_counter(&self, sensor_id: u8, counter: u64) -> anyhow::Result<()> {
+        let connection = self.connection()?;
+
+        connection.execute(
+            "INSERT INTO replay_counters (sensor_id, counter) VALUES (?1, ?2)
+             ON CONFLICT (sensor_id) DO UPDATE SET counter = MAX(counter, excluded.counter)",
+            params![sensor_id, to_sql(counter)],
+        )?;
+
+        Ok(())
+    }
+
+    fn save_measurement(
+        &self,
+        fingerprint: u64,
+        sensor_id: u8,
+        rssi: i8,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection()?;
+
+        connection.execute(
+            "INSERT INTO measurements (fingerprint, sensor_id, rssi, timestamp)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![to_sql(fingerprint), sensor_id, rssi, to_sql(timestamp)],
+        )?;
+
+        Ok(())
+    }
+
+    fn save_trilateration(&self, trilateration: &Trilateration) -> anyhow::Result<()> {
+        let connection = self.connection()?;
+
+        connection.execute(
+            "INSERT INTO trilaterations (fingerprint, timestamp, data) VALUES (?1, ?2, ?3)",
+            params![
+                to_sql(trilateration.fingerprint),
+                to_sql(trilateration.timestamp),
+                serde_json::to_string(trilateration)?
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_trilaterations(&self) -> anyhow::Result<Vec<Trilateration>> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare(
+            "SELECT data FROM trilaterations AS t
+             WHERE timestamp = (
+                 SELECT MAX(timestamp) FROM trilaterations WHERE fingerprint = t.fingerprint
+             )",
+        )?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+    }
+
+    fn load_track(
+        &self,
+        fingerprint: u64,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> anyhow::Result<Vec<Trilateration>> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare(
+            "SELECT data FROM trilaterations
+             WHERE fingerprint = ?1 AND timestamp >= ?2 AND timestamp <= ?3
+             ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map(
+            params![
+                to_sql(fingerprint),
+                to_sql(since.unwrap_or(0)),
+                to_sql(until.unwrap_or(i64::MAX as u64))
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+
+        rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+    }
+
+    fn prune(&self, measurements_before: u64, trilaterations_before: u64) -> anyhow::Result<()> {
+        let connection = self.connection()?;
+
+        connection.execute(
+            "DELETE FROM measurements WHERE timestamp < ?1",
+            params![to_sql(measurements_before)],
+        )?;
+        connection.execute(
+            "DELETE FROM trilaterations WHERE timestamp < ?1",
+            params![to_sql(trilaterations_before)],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sensor_lib::Environment;
+
+    use super::*;
+    use crate::rssi::ErrorEllipse;
+    use crate::sensors::TrackedPosition;
+
+    fn storage() -> SqliteStorage {
+        SqliteStorage::open(":memory:").unwrap()
+    }
+
+    fn sensor(id: u8, x: f64) -> Sensor {
+        Sensor {
+            id,
+            x,
+            y: 2.0,
+            lat: 50.5648,
+            lon: 9.6845,
+            environment: Environment::InBuildingLOS,
+            rssi_at_1m: -65.0,
+            rssi_offset: 1.5,
+            path_loss: None,
+            label: Some(format!("sensor {id}")),
+            enabled: true,
+            conflict: None,
+        }
+    }
+
+    fn trilateration(fingerprint: u64, timestamp: u64) -> Trilateration {
+        let error_ellipse = ErrorEllipse {
+            semi_major: 1.0,
+            semi_minor: 0.5,
+            orientation: 0.0,
+        };
+
+        Trilateration {
+            fingerprint,
+            members: vec![fingerprint],
+            timestamp,
+            y: 1.0,
+            x: timestamp as f64,
+            lat: None,
+            lon: None,
+            residual: 0.1,
+            covariance: None,
+            error_ellipse: None,
+            sensors: 3,
+            samples: 30,
+            tracked: TrackedPosition {
+                y: 1.0,
+                x: timestamp as f64,
+                lat: None,
+                lon: None,
+                vy: 0.0,
+                vx: 0.0,
+                speed: 0.0,
+                heading: 0.0,
+                error_ellipse,
+            },
+        }
+    }
+
+    fn timestamps(trilaterations: &[Trilateration]) -> Vec<u64> {
+        trilaterations.iter().map(|t| t.timestamp).collect()
+    }
+
+    fn measurements(storage: &SqliteStorage) -> Vec<u64> {
+        let connection = storage.connection().unwrap();
+        let mut statement = connection
+            .prepare("SELECT timestamp FROM measurements ORDER BY timestamp")
+            .unwrap();
+
+        statement
+            .query_map([], |row| row.get::<_, i64>(0))
+            .unwrap()
+            .map(|timestamp| timestamp.unwrap() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_sensors() {
+        let storage = storage();
+
+        storage.save_sensor(&sensor(2, 4.0)).unwrap();
+        storage.save_sensor(&sensor(1, 0.0)).unwrap();
+        storage.save_sensor(&sensor(2, 8.0)).unwrap();
+
+        assert!(storage.load_sensors().unwrap() == vec![sensor(1, 0.0), sensor(2, 8.0)]);
+    }
+
+    #[test]
+    fn deletes_sensors_but_keeps_their_replay_counters() {
+        let storage = storage();
+
+        storage.save_sensor(&sensor(1, 0.0)).unwrap();
+        storage.save_sensor(&sensor(2, 4.0)).unwrap();
+        storage.save_replay_counter(1, 42).unwrap();
+        storage.delete_sensor(1).unwrap();
+        storage.delete_sensor(3).unwrap();
+
+        assert!(storage.load_sensors().unwrap() == vec![sensor(2, 4.0)]);
+        assert_eq!(storage.load_replay_counters().unwrap(), vec![(1, 42)]);
+    }
+
+    #[test]
+    fn keeps_the_highest_replay_counter() {
+        let storage = storage();
+
+        storage.save_replay_counter(1, 10).unwrap();
+        storage.save_replay_counter(1, 7).unwrap();
+        storage.save_replay_counter(2, u64::MAX - 1).unwrap();
+
+        let mut counters = storage.load_replay_counters().unwrap();
+        counters.sort_unstable();
+
+        assert_eq!(counters, vec![(1, 10), (2, u64::MAX - 1)]);
+    }
+
+    #[test]
+    fn loads_the_latest_trilaterations_and_tracks() {
+        let storage = storage();
+
+        for timestamp in [1_000, 3_000, 2_000] {
+            storage
+                .save_trilateration(&trilateration(u64::MAX, timestamp))
+                .unwrap();
+        }
+        storage
+            .save_trilateration(&trilateration(7, 1_500))
+            .unwrap();
+
+        let mut latest = storage.load_trilaterations().unwrap();
+        latest.sort_unstable_by_key(|t| t.fingerprint);
+
+        assert_eq!(timestamps(&latest), vec![1_500, 3_000]);
+        assert_eq!(latest[1].fingerprint, u64::MAX);
+
+        let track = storage.load_track(u64::MAX, None, None).unwrap();
+        assert_eq!(timestamps(&track), vec![1_000, 2_000, 3_000]);
+
+        let track = storage
+            .load_track(u64::MAX, Some(1_500), Some(2_000))
+            .unwrap();
+        assert_eq!(timestamps(&track), vec![2_000]);
+    }
+
+    #[test]
+    fn prunes_old_measurements_and_trilaterations() {
+        let storage = storage();
+
+        for timestamp in [1_000, 2_000, 3_000] {
+            storage.save_measurement(0xabcd, 1, -60, timestamp).unwrap();
+            storage
+                .save_trilateration(&trilateration(0xabcd, timestamp))
+                .unwrap();
+        }
+
+        storage.prune(3_000, 2_000).unwrap();
+
+        assert_eq!(measurements(&storage), vec![3_000]);
+        assert_eq!(
+            timestamps(&storage.load_track(0xabcd, None, None).unwrap()),
+            vec![2_000, 3_000]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use nalgebra::{Matrix2, Matrix2x4, Matrix4, Vector2, Vector4};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::config::seconds;
+
+const INITIAL_VELOCITY_VARIANCE: f64 = 4.0;
+/// Variance of the first fix of a track if the fix has no covariance.
+const INITIAL_POSITION_VARIANCE: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerConfig {
+    /// Spectral density of the white noise acceleration in m²/s³.
+    pub acceleration_noise: f64,
+    /// Time without a fix after which the track is restarted instead of predicted.
+    #[serde(with = "seconds")]
+    #[schema(
+        value_type = f64,
+        default = json!(TrackerConfig::default().max_gap.as_secs_f64())
+    )]
+    pub max_gap: Duration,
+    /// Time without a fix after which the position of a device is removed.
+    #[serde(with = "seconds")]
+    #[schema(
+        value_type = f64,
+        default = json!(TrackerConfig::default().device_expiry.as_secs_f64())
+    )]
+    pub device_expiry: Duration,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            acceleration_noise: 0.5,
+            max_gap: Duration::from_secs(30),
+            device_expiry: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Constant velocity Kalman filter over successive position fixes with the state
+/// `[x, y, vx, vy]`.
+#[derive(Clone)]
+pub struct MotionTracker {
+    state: Vector4<f64>,
+    covariance: Matrix4<f64>,
+    timestamp: u64,
+}
+
+impl MotionTracker {
+    pub fn new(position: Vector2<f64>, covariance: Option<&Matrix2<f64>>, timestamp: u64) -> Self {
+        let mut initial_covariance = Matrix4::from_diagonal_element(INITIAL_VELOCITY_VARIANCE);
+        initial_covariance.fixed_view_mut::<2, 2>(0, 0).copy_from(
+            &covariance
+                .copied()
+                .unwrap_or_else(|| Matrix2::from_diagonal_element(INITIAL_POSITION_VARIANCE)),
+        );
+
+        Self {
+            state: Vector4::new(position.x, position.y, 0.0, 0.0),
+            covariance: initial_covariance,
+            timestamp,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Corrects the track with the fix, a fix without covariance only advances the prediction.
+    pub fn update(
+        &mut self,
+        config: &TrackerConfig,
+        position: Vector2<f64>,
+        covariance: Option<&Matrix2<f64>>,
+        timestamp: u64,
+    ) {
+        let elapsed = timestamp.saturating_sub(self.timestamp);
+
+        if elapsed > config.max_gap.as_millis() as u64 {
+            *self = Self::new(position, covariance, timestamp);
+            return;
+        }
+
+        let dt = elapsed as f64 / 1000.0;
+        let q = config.acceleration_noise;
+
+        #[rustfmt::skip]
+        let transition = Matrix4::new(
+            1.0, 0.0, dt, 0.0,
+            0.0, 1.0, 0.0, dt,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let (dt2, dt3) = (dt.powi(2) / 2.0, dt.powi(3) / 3.0);
+        #[rustfmt::skip]
+        let process_noise = Matrix4::new(
+            dt3, 0.0, dt2, 0.0,
+            0.0, dt3, 0.0, dt2,
+            dt2, 0.0, dt, 0.0,
+            0.0, dt2, 0.0, dt,
+        ) * q;
+
+        let state = transition * self.state;
+        let predicted = transition * self.covariance * transition.transpose() + process_noise;
+
+        let Some(covariance) = covariance else {
+            self.state = state;
+            self.covariance = predicted;
+            self.timestamp = timestamp;
+            return;
+        };
+
+        let observation = Matrix2x4::new(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+        let innovation = position - observation * state;
+        let innovation_covariance = observation * predicted * observation.transpose() + covariance;
+
+        let Some(inverse) = innovation_covariance.try_inverse() else {
+            return;
+        };
+        let gain = predicted * observation.transpose() * inverse;
+
+        self.state = state + gain * innovation;
+        self.covariance = (Matrix4::identity() - gain * observation) * predicted;
+        self.timestamp = timestamp;
+    }
+
+    pub fn position(&self) -> Vector2<f64> {
+        Vector2::new(self.state[0], self.state[1])
+    }
+
+    pub fn velocity(&self) -> Vector2<f64> {
+        Vector2::new(self.state[2], self.state[3])
+    }
+
+    /// Direction of movement in degrees, counterclockwise from the x axis.
+    pub fn heading(&self) -> f64 {
+        self.state[3]
+            .atan2(self.state[2])
+            .to_degrees()
+            .rem_euclid(360.0)
+    }
+
+    pub fn position_covariance(&self) -> Matrix2<f64> {
+        self.covariance.fixed_view::<2, 2>(0, 0).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIX_VARIANCE: f64 = 0.25;
+
+    /// Fixes every second of a device that moves with the given velocity.
+    fn track(velocity: Vector2<f64>, fixes: u64) -> MotionTracker {
+        let config = TrackerConfig::default();
+        let covariance = Matrix2::from_diagonal_element(FIX_VARIANCE);
+        let start = Vector2::new(3.0, -2.0);
+
+        let mut tracker = MotionTracker::new(start, Some(&covariance), 0);
+        for i in 1..fixes {
+            let position = start + velocity * i as f64;
+            tracker.update(&config, position, Some(&covariance), i * 1000);
+        }
+
+        tracker
+    }
+
+    #[test]
+    fn converges_on_a_constant_velocity() {
+        let velocity = Vector2::new(1.5, -0.5);
+        let tracker = track(velocity, 30);
+
+        assert!(
+            (tracker.velocity() - velocity).norm() < 0.05,
+            "{}",
+            tracker.velocity()
+        );
+        assert!(
+            (tracker.position() - Vector2::new(3.0 + 1.5 * 29.0, -2.0 - 0.5 * 29.0)).norm() < 0.1
+        );
+
+        let heading = (-0.5f64).atan2(1.5).to_degrees().rem_euclid(360.0);
+        assert!(
+            (tracker.heading() - heading).abs() < 1.0,
+            "heading: {}",
+            tracker.heading()
+        );
+        assert_eq!(tracker.timestamp(), 29_000);
+    }
+
+    #[test]
+    fn reports_the_heading_counterclockwise_from_the_x_axis() {
+        for (velocity, heading) in [
+            (Vector2::new(1.0, 0.0), 0.0),
+            (Vector2::new(0.0, 1.0), 90.0),
+            (Vector2::new(-1.0, 0.0), 180.0),
+            (Vector2::new(0.0, -1.0), 270.0),
+        ] {
+            let tracker = track(velocity, 30);
+
+            assert!(
+                (tracker.heading() - heading).abs() < 1.0,
+                "heading: {}",
+                tracker.heading()
+            );
+        }
+    }
+
+    #[test]
+    fn shrinks_the_uncertainty_below_a_single_fix() {
+        let tracker = track(Vector2::new(1.0, 1.0), 30);
+        let covariance = tracker.position_covariance();
+
+        assert!(covariance[(0, 0)] < FIX_VARIANCE && covariance[(1, 1)] < FIX_VARIANCE);
+    }
+
+    #[test]
+    fn predicts_fixes_without_covariance() {
+        let config = TrackerConfig::default();
+        let mut tracker = track(Vector2::new(1.0, 0.0), 30);
+        let position = tracker.position();
+        let variance = tracker.position_covariance()[(0, 0)];
+
+        tracker.update(&config, Vector2::new(-50.0, 80.0), None, 31_000);
+
+        assert!((tracker.position() - position - Vector2::new(2.0, 0.0)).norm() < 0.05);
+        assert!(tracker.position_covariance()[(0, 0)] > variance);
+        assert_eq!(tracker.timestamp(), 31_000);
+    }
+
+    #[test]
+    fn restarts_after_a_gap() {
+        let config = TrackerConfig::default();
+        let covariance = Matrix2::from_diagonal_element(FIX_VARIANCE);
+        let mut tracker = track(Vector2::new(2.0, 0.0), 10);
+
+        let gap = 9_000 + config.max_gap.as_millis() as u64 + 1;
+        tracker.update(&config, Vector2::new(100.0, 50.0), Some(&covariance), gap);
+
+        assert_eq!(tracker.position(), Vector2::new(100.0, 50.0));
+        assert_eq!(tracker.velocity(), Vector2::zeros());
+        assert_eq!(tracker.timestamp(), gap);
+    }
+}
@@ -3,8 +3,25 @@
 use core::net::Ipv4Addr;
 use serde::{Deserialize, Serialize};
 
+pub mod probe;
+pub mod protocol;
+
+use probe::ProbeSignature;
+use protocol::{MAX_FRAME_SIZE, MAX_HEADER_SIZE, SensorKey, TAG_SIZE};
+
 const MAX_SSID_LENGTH: usize = 32;
 const MAX_WIFI_PASSWORD_LENGTH: usize = 63;
+pub const MAX_FIRMWARE_VERSION_LENGTH: usize = 16;
+
+/// Worst case postcard size of a [`Measurement`], with the fingerprint, signature, sequence number
+/// and timestamp varint encoded.
+const MAX_MEASUREMENT_SIZE: usize = 1 + 10 + 11 + 4 + 1 + 10;
+/// Worst case size of a batch frame without its measurements: the header, the message variant,
+/// the varint encoded length of the batch and the tag.
+const MAX_BATCH_OVERHEAD: usize = MAX_HEADER_SIZE + 1 + 2 + TAG_SIZE;
+/// Number of measurements that always fit into a single frame.
+pub const MAX_BATCH_MEASUREMENTS: usize =
+    (MAX_FRAME_SIZE - MAX_BATCH_OVERHEAD) / MAX_MEASUREMENT_SIZE;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SensorConfig {
@@ -20,21 +37,86 @@ pub struct SensorConfig {
     pub latitude: f64,
     pub longitude: f64,
     pub environment: Environment,
+
+    /// Authenticates the frames of the node, the backend needs the same key for the sensor ID.
+    pub key: SensorKey,
+    /// Initial epoch of the frame counter, must be greater than the epoch of any previous
+    /// configuration upload with the same key.
+    pub epoch: u32,
 }
 
+// Boxing the batch would require an allocator on the nodes.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Serialize, Deserialize)]
-pub struct SensorPacket {
+pub enum SensorMessage {
+    /// Sent once after the node booted.
+    Hello(SensorInfo),
+    /// Sent periodically to keep the position and metadata of the node up to date.
+    Heartbeat(SensorInfo),
+    Measurement(Measurement),
+    MeasurementBatch(MeasurementBatch),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorInfo {
     pub sensor_id: u8,
     pub y: f64,
     pub x: f64,
     pub latitude: f64,
     pub longitude: f64,
     pub environment: Environment,
+    pub firmware_version: heapless::String<MAX_FIRMWARE_VERSION_LENGTH>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Measurement {
+    pub sensor_id: u8,
     pub fingerprint: u64,
+    /// Signature of the probe request, identifies the device while it randomizes its MAC address.
+    pub signature: Option<ProbeSignature>,
+    /// 802.11 sequence number of the probe request, continues when the MAC address changes.
+    pub sequence_number: Option<u16>,
     pub rssi: i8,
+    /// Uptime of the node in milliseconds when the frame was received.
+    pub timestamp_ms: u64,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Measurements collected by a node and sent in a single frame once the batch is full or a
+/// timeout elapsed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MeasurementBatch {
+    pub measurements: heapless::Vec<Measurement, MAX_BATCH_MEASUREMENTS>,
+}
+
+impl MeasurementBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a measurement to the batch, returns it back if the batch is full.
+    pub fn push(&mut self, measurement: Measurement) -> Result<(), Measurement> {
+        self.measurements.push(measurement)
+    }
+
+    pub fn len(&self) -> usize {
+        self.measurements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.measurements.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.measurements.is_full()
+    }
+
+    /// Takes all measurements out of the batch, leaving it empty.
+    pub fn take(&mut self) -> Self {
+        core::mem::take(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Environment {
     FreeSpace,
     UrbanArea,
@@ -58,3 +140,172 @@ impl Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Frame, FrameError, FrameHeader, KEY_SIZE, decode_frame, encode_frame};
+
+    const KEY: SensorKey = [0x42; KEY_SIZE];
+
+    fn worst_case_header() -> FrameHeader {
+        FrameHeader {
+            sensor_id: u8::MAX,
+            epoch: u32::MAX,
+            sequence: u32::MAX,
+            uptime_ms: u64::MAX,
+        }
+    }
+
+    fn worst_case_measurement() -> Measurement {
+        Measurement {
+            sensor_id: u8::MAX,
+            fingerprint: u64::MAX,
+            signature: Some(ProbeSignature(u64::MAX)),
+            sequence_number: Some(u16::MAX),
+            rssi: i8::MIN,
+            timestamp_ms: u64::MAX,
+        }
+    }
+
+    fn full_batch() -> MeasurementBatch {
+        let mut batch = MeasurementBatch::new();
+
+        while !batch.is_full() {
+            batch.push(worst_case_measurement()).unwrap();
+        }
+
+        batch
+    }
+
+    #[test]
+    fn full_batch_fits_into_frame() {
+        let frame = Frame {
+            header: worst_case_header(),
+            payload: SensorMessage::MeasurementBatch(full_batch()),
+        };
+
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let len = encode_frame(&frame, &KEY, &mut buf).unwrap().len();
+
+        assert!(len <= MAX_FRAME_SIZE);
+        // The batch should not waste more than a single measurement of the frame.
+        assert!(MAX_FRAME_SIZE - len < MAX_MEASUREMENT_SIZE);
+    }
+
+    #[test]
+    fn measurement_size_is_worst_case() {
+        let mut buf = [0u8; MAX_MEASUREMENT_SIZE + 1];
+        let len = postcard::to_slice(&worst_case_measurement(), &mut buf)
+            .unwrap()
+            .len();
+
+        assert_eq!(len, MAX_MEASUREMENT_SIZE);
+    }
+
+    #[test]
+    fn push_rejects_measurement_when_full() {
+        let mut batch = full_batch();
+
+        assert_eq!(batch.len(), MAX_BATCH_MEASUREMENTS);
+        assert!(batch.push(worst_case_measurement()).is_err());
+
+        let taken = batch.take();
+
+        assert_eq!(taken.len(), MAX_BATCH_MEASUREMENTS);
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn full_batch_roundtrips() {
+        let frame = Frame {
+            header: worst_case_header(),
+            payload: SensorMessage::MeasurementBatch(full_batch()),
+        };
+
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let data = encode_frame(&frame, &KEY, &mut buf).unwrap();
+        let decoded = decode_frame::<SensorMessage>(data, |_| Some(KEY)).unwrap();
+
+        assert_eq!(decoded.header, frame.header);
+        let SensorMessage::MeasurementBatch(batch) = decoded.payload else {
+            panic!("expected a measurement batch");
+        };
+        assert_eq!(batch.len(), MAX_BATCH_MEASUREMENTS);
+    }
+
+    #[test]
+    fn oversized_batch_is_rejected() {
+        // A batch with one measurement more than the capacity, as sent by a misbehaving node. The
+        // variant index of `SensorMessage::MeasurementBatch` is encoded as a single byte.
+        let mut measurements = heapless::Vec::<Measurement, { MAX_BATCH_MEASUREMENTS + 1 }>::new();
+        while !measurements.is_full() {
+            measurements.push(worst_case_measurement()).unwrap();
+        }
+
+        let frame = Frame {
+            header: worst_case_header(),
+            payload: (3u8, measurements),
+        };
+
+        let mut buf = [0u8; 2 * MAX_FRAME_SIZE];
+        let data = encode_frame(&frame, &KEY, &mut buf).unwrap();
+
+        assert_eq!(
+            decode_frame::<SensorMessage>(data, |_| Some(KEY)).unwrap_err(),
+            FrameError::Malformed
+        );
+    }
+
+    #[test]
+    fn too_small_buffer_is_reported() {
+        let frame = Frame {
+            header: worst_case_header(),
+            payload: SensorMessage::MeasurementBatch(full_batch()),
+        };
+
+        let mut buf = [0u8; MAX_FRAME_SIZE / 2];
+
+        assert_eq!(
+            encode_frame(&frame, &KEY, &mut buf).unwrap_err(),
+            FrameError::BufferTooSmall
+        );
+    }
+
+    fn encoded_frame(buf: &mut [u8]) -> &mut [u8] {
+        let frame = Frame {
+            header: worst_case_header(),
+            payload: SensorMessage::MeasurementBatch(full_batch()),
+        };
+
+        encode_frame(&frame, &KEY, buf).unwrap()
+    }
+
+    #[test]
+    fn tampered_frame_is_rejected() {
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let data = encoded_frame(&mut buf);
+        let last = data.len() - TAG_SIZE - 1;
+        data[last] ^= 1;
+
+        assert_eq!(
+            decode_frame::<SensorMessage>(data, |_| Some(KEY)).unwrap_err(),
+            FrameError::InvalidTag
+        );
+    }
+
+    #[test]
+    fn frame_with_wrong_key_is_rejected() {
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let data = encoded_frame(&mut buf);
+
+        assert_eq!(
+            decode_frame::<SensorMessage>(data, |_| Some([0; KEY_SIZE])).unwrap_err(),
+            FrameError::InvalidTag
+        );
+        assert_eq!(
+            decode_frame::<SensorMessage>(data, |_| None).unwrap_err(),
+            FrameError::UnknownSensor(u8::MAX)
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Parsing of 802.11 probe requests into a signature of the capabilities a device advertises,
+//! which stays the same while the device randomizes its MAC address.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+const MANAGEMENT_HEADER_SIZE: usize = 24;
+const FCS_SIZE: usize = 4;
+
+const PROBE_REQUEST_FRAME_CONTROL: u8 = 0x40;
+
+const SSID: u8 = 0;
+const SUPPORTED_RATES: u8 = 1;
+const HT_CAPABILITIES: u8 = 45;
+const EXTENDED_SUPPORTED_RATES: u8 = 50;
+const EXTENDED_CAPABILITIES: u8 = 127;
+const VHT_CAPABILITIES: u8 = 191;
+const VENDOR_SPECIFIC: u8 = 221;
+const EXTENSION: u8 = 255;
+
+const HE_CAPABILITIES: u8 = 35;
+
+/// Length of the OUI and the vendor specific type, the rest of a vendor element may change
+/// between probe requests.
+const VENDOR_HEADER_SIZE: usize = 4;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProbeError {
+    NotProbeRequest,
+    Truncated,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::NotProbeRequest => write!(f, "frame is not a probe request"),
+            ProbeError::Truncated => write!(f, "probe request is truncated"),
+        }
+    }
+}
+
+impl core::error::Error for ProbeError {}
+
+/// Hash of the supported rates, the HT, VHT, HE and extended capabilities, the vendor elements
+/// and the order of all information elements of a probe request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProbeSignature(pub u64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeRequest<'a> {
+    pub transmitter: [u8; 6],
+    pub sequence_number: u16,
+    elements: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Element<'a> {
+    pub id: u8,
+    pub data: &'a [u8],
+}
+
+pub struct Elements<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Result<Element<'a>, ProbeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let Some((&[id, len], rest)) = self.data.split_first_chunk::<2>() else {
+            self.data = &[];
+            return Some(Err(ProbeError::Truncated));
+        };
+
+        let Some((data, rest)) = rest.split_at_checked(len as usize) else {
+            self.data = &[];
+            return Some(Err(ProbeError::Truncated));
+        };
+
+        self.data = rest;
+
+        Some(Ok(Element { id, data }))
+    }
+}
+
+impl<'a> ProbeRequest<'a> {
+    /// Parses a probe request management frame, `with_fcs` if the frame check sequence is still
+    /// appended to the frame.
+    pub fn parse(frame: &'a [u8], with_fcs: bool) -> Result<Self, ProbeError> {
+        let frame = match with_fcs {
+            true => &frame[..frame.len().saturating_sub(FCS_SIZE)],
+            false => frame,
+        };
+
+        if frame.len() < MANAGEMENT_HEADER_SIZE {
+            return Err(ProbeError::Truncated);
+        }
+
+        // Type and subtype are in the first byte, the flags in the second.
+        if frame[0] != PROBE_REQUEST_FRAME_CONTROL {
+            return Err(ProbeError::NotProbeRequest);
+        }
+
+        let mut transmitter = [0; 6];
+        transmitter.copy_from_slice(&frame[10..16]);
+
+        let request = Self {
+            transmitter,
+            sequence_number: u16::from_le_bytes([frame[22], frame[23]]) >> 4,
+            elements: &frame[MANAGEMENT_HEADER_SIZE..],
+        };
+
+        if let Some(Err(e)) = request.elements().find(Result::is_err) {
+            return Err(e);
+        }
+
+        Ok(request)
+    }
+
+    pub fn elements(&self) -> Elements<'a> {
+        Elements {
+            data: self.elements,
+        }
+    }
+
+    pub fn ssid(&self) -> Option<&'a [u8]> {
+        self.elements()
+            .flatten()
+            .find(|e| e.id == SSID)
+            .map(|e| e.data)
+    }
+
+    pub fn signature(&self) -> ProbeSignature {
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut update = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+
+        for element in self.elements().flatten() {
+            // The content of elements like the SSID or the current channel depends on the
+            // network the device is looking for, only their position is part of the signature.
+            let content = match element.id {
+                SUPPORTED_RATES
+                | EXTENDED_SUPPORTED_RATES
+                | HT_CAPABILITIES
+                | VHT_CAPABILITIES
+                | EXTENDED_CAPABILITIES => element.data,
+                VENDOR_SPECIFIC => &element.data[..element.data.len().min(VENDOR_HEADER_SIZE)],
+                EXTENSION => match element.data.first() {
+                    Some(&HE_CAPABILITIES) => element.data,
+                    _ => &element.data[..element.data.len().min(1)],
+                },
+                _ => &[],
+            };
+
+            update(&[element.id, content.len() as u8]);
+            update(content);
+        }
+
+        ProbeSignature(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPHONE_WILDCARD: &[u8] = include_bytes!("../fixtures/probe/iphone_wildcard.bin");
+    const IPHONE_DIRECTED: &[u8] = include_bytes!("../fixtures/probe/iphone_directed.bin");
+    const PIXEL_WILDCARD: &[u8] = include_bytes!("../fixtures/probe/pixel_wildcard.bin");
+
+    fn parse(frame: &[u8]) -> ProbeRequest<'_> {
+        ProbeRequest::parse(frame, false).unwrap()
+    }
+
+    #[test]
+    fn parses_header() {
+        let request = parse(IPHONE_DIRECTED);
+
+        assert_eq!(request.transmitter, [0x7a, 0x42, 0x9b, 0x10, 0xc3, 0x77]);
+        assert_eq!(request.sequence_number, 1240);
+        assert_eq!(request.ssid(), Some(&b"HomeNet"[..]));
+        assert_eq!(request.elements().count(), 10);
+    }
+
+    #[test]
+    fn signature_ignores_address_ssid_and_channel() {
+        let wildcard = parse(IPHONE_WILDCARD);
+        let directed = parse(IPHONE_DIRECTED);
+
+        assert_ne!(wildcard.transmitter, directed.transmitter);
+        assert_ne!(wildcard.ssid(), directed.ssid());
+        assert_eq!(wildcard.signature(), directed.signature());
+    }
+
+    #[test]
+    fn signature_differs_between_devices() {
+        assert_ne!(
+            parse(IPHONE_WILDCARD).signature(),
+            parse(PIXEL_WILDCARD).signature()
+        );
+    }
+
+    #[test]
+    fn signature_depends_on_element_order() {
+        // Swaps the supported rates and the SSID, both at the start of the elements.
+        let mut frame = [0u8; 256];
+        let frame = &mut frame[..IPHONE_WILDCARD.len()];
+        frame.copy_from_slice(IPHONE_WILDCARD);
+        frame[MANAGEMENT_HEADER_SIZE..MANAGEMENT_HEADER_SIZE + 8].copy_from_slice(&[
+            SUPPORTED_RATES,
+            4,
+            0x02,
+            0x04,
+            0x0b,
+            0x16,
+            SSID,
+            0,
+        ]);
+
+        assert_ne!(parse(frame).signature(), parse(IPHONE_WILDCARD).signature());
+    }
+
+    #[test]
+    fn strips_frame_check_sequence() {
+        let mut frame = [0u8; 256];
+        let len = PIXEL_WILDCARD.len() + FCS_SIZE;
+        frame[..PIXEL_WILDCARD.len()].copy_from_slice(PIXEL_WILDCARD);
+        frame[PIXEL_WILDCARD.len()..len].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let request = ProbeRequest::parse(&frame[..len], true).unwrap();
+
+        assert_eq!(request.signature(), parse(PIXEL_WILDCARD).signature());
+        assert_eq!(
+            ProbeRequest::parse(&frame[..len], false).unwrap_err(),
+            ProbeError::Truncated
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_and_other_frames() {
+        assert_eq!(
+            ProbeRequest::parse(&IPHONE_WILDCARD[..IPHONE_WILDCARD.len() - 1], false).unwrap_err(),
+            ProbeError::Truncated
+        );
+        assert_eq!(
+            ProbeRequest::parse(&IPHONE_WILDCARD[..MANAGEMENT_HEADER_SIZE - 1], false).unwrap_err(),
+            ProbeError::Truncated
+        );
+
+        // Same frame with the subtype of a beacon.
+        let mut frame = [0u8; 256];
+        let frame = &mut frame[..IPHONE_WILDCARD.len()];
+        frame.copy_from_slice(IPHONE_WILDCARD);
+        frame[0] = 0x80;
+
+        assert_eq!(
+            ProbeRequest::parse(frame, false).unwrap_err(),
+            ProbeError::NotProbeRequest
+        );
+    }
+}
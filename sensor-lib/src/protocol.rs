@@ -0,0 +1,330 @@
+use core::fmt;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Identifies sensor frames, "SN" in ASCII.
+pub const PROTOCOL_MAGIC: [u8; 2] = [0x53, 0x4E];
+pub const PROTOCOL_VERSION: u8 = 5;
+
+/// Fits into a single datagram without IP fragmentation on Ethernet and WiFi links.
+pub const MAX_FRAME_SIZE: usize = 1024;
+
+pub const KEY_SIZE: usize = 32;
+/// Size of the HMAC-SHA256 tag appended to every frame, truncated to 128 bits.
+pub const TAG_SIZE: usize = 16;
+
+pub type SensorKey = [u8; KEY_SIZE];
+
+const PREAMBLE_SIZE: usize = PROTOCOL_MAGIC.len() + 1;
+/// Worst case size of the preamble and the header, with the epoch, sequence and uptime varint
+/// encoded.
+pub(crate) const MAX_HEADER_SIZE: usize = PREAMBLE_SIZE + 1 + 5 + 5 + 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameHeader {
+    pub sensor_id: u8,
+    /// Provisioned with the key and incremented by the node whenever the sequence wraps, so that
+    /// the counter of a key never repeats.
+    pub epoch: u32,
+    /// Incremented by the node for every frame, used to detect packet loss.
+    pub sequence: u32,
+    /// Milliseconds since the node booted.
+    pub uptime_ms: u64,
+}
+
+impl FrameHeader {
+    /// Strictly increasing number of the frame, used for replay protection.
+    pub fn counter(&self) -> u64 {
+        (self.epoch as u64) << 32 | self.sequence as u64
+    }
+}
+
+/// A versioned frame on the wire: the magic, the protocol version and the postcard encoded
+/// header followed by the postcard encoded payload and the authentication tag over all of it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Frame<T> {
+    pub header: FrameHeader,
+    pub payload: T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameError {
+    TooShort,
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    Malformed,
+    UnknownSensor(u8),
+    InvalidTag,
+    BufferTooSmall,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooShort => write!(f, "frame is shorter than its preamble"),
+            FrameError::InvalidMagic => write!(f, "frame does not start with the protocol magic"),
+            FrameError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported protocol version {} (supported: {})",
+                version, PROTOCOL_VERSION
+            ),
+            FrameError::Malformed => write!(f, "frame header or payload is malformed"),
+            FrameError::UnknownSensor(sensor_id) => {
+                write!(f, "no key registered for sensor {}", sensor_id)
+            }
+            FrameError::InvalidTag => write!(f, "frame authentication failed"),
+            FrameError::BufferTooSmall => write!(f, "frame does not fit into the buffer"),
+        }
+    }
+}
+
+impl core::error::Error for FrameError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyError {
+    InvalidLength(usize),
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::InvalidLength(length) => write!(
+                f,
+                "key must be {} hex encoded bytes, got {} characters",
+                KEY_SIZE, length
+            ),
+            KeyError::InvalidCharacter(c) => write!(f, "invalid hex character {:?} in key", c),
+        }
+    }
+}
+
+impl core::error::Error for KeyError {}
+
+/// Parses a hex encoded key, as shared by the backend configuration and the node provisioning.
+pub fn parse_key(s: &str) -> Result<SensorKey, KeyError> {
+    let length = s.chars().count();
+    if length != KEY_SIZE * 2 {
+        return Err(KeyError::InvalidLength(length));
+    }
+
+    let mut digits = s.chars().map(|c| {
+        c.to_digit(16)
+            .map(|d| d as u8)
+            .ok_or(KeyError::InvalidCharacter(c))
+    });
+
+    let mut key = [0u8; KEY_SIZE];
+    for byte in key.iter_mut() {
+        // The length was checked above, so there are two digits left for every byte.
+        let (high, low) = (digits.next().unwrap()?, digits.next().unwrap()?);
+        *byte = high << 4 | low;
+    }
+
+    Ok(key)
+}
+
+fn mac(key: &SensorKey, data: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac
+}
+
+pub fn encode_frame<'a, T: Serialize>(
+    frame: &Frame<T>,
+    key: &SensorKey,
+    buf: &'a mut [u8],
+) -> Result<&'a mut [u8], FrameError> {
+    if buf.len() < PREAMBLE_SIZE + TAG_SIZE {
+        return Err(FrameError::BufferTooSmall);
+    }
+
+    buf[..PROTOCOL_MAGIC.len()].copy_from_slice(&PROTOCOL_MAGIC);
+    buf[PROTOCOL_MAGIC.len()] = PROTOCOL_VERSION;
+
+    let body_end = buf.len() - TAG_SIZE;
+    let len = PREAMBLE_SIZE
+        + postcard::to_slice(frame, &mut buf[PREAMBLE_SIZE..body_end])
+            .map_err(|_| FrameError::BufferTooSmall)?
+            .len();
+
+    let tag = mac(key, &buf[..len]).finalize().into_bytes();
+    buf[len..len + TAG_SIZE].copy_from_slice(&tag[..TAG_SIZE]);
+
+    Ok(&mut buf[..len + TAG_SIZE])
+}
+
+/// Decodes and authenticates a frame with the key returned for the sensor ID in its header.
+pub fn decode_frame<'a, T: Deserialize<'a>>(
+    data: &'a [u8],
+    key: impl FnOnce(u8) -> Option<SensorKey>,
+) -> Result<Frame<T>, FrameError> {
+    if data.len() < PREAMBLE_SIZE {
+        return Err(FrameError::TooShort);
+    }
+
+    if data[..PROTOCOL_MAGIC.len()] != PROTOCOL_MAGIC {
+        return Err(FrameError::InvalidMagic);
+    }
+
+    match data[PROTOCOL_MAGIC.len()] {
+        PROTOCOL_VERSION => {}
+        version => return Err(FrameError::UnsupportedVersion(version)),
+    }
+
+    if data.len() < PREAMBLE_SIZE + TAG_SIZE {
+        return Err(FrameError::TooShort);
+    }
+
+    let (body, tag) = data.split_at(data.len() - TAG_SIZE);
+
+    let (header, payload) = postcard::take_from_bytes::<FrameHeader>(&body[PREAMBLE_SIZE..])
+        .map_err(|_| FrameError::Malformed)?;
+
+    let key = key(header.sensor_id).ok_or(FrameError::UnknownSensor(header.sensor_id))?;
+
+    mac(&key, body)
+        .verify_truncated_left(tag)
+        .map_err(|_| FrameError::InvalidTag)?;
+
+    Ok(Frame {
+        header,
+        payload: postcard::from_bytes(payload).map_err(|_| FrameError::Malformed)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: SensorKey = [7; KEY_SIZE];
+
+    fn frame(sequence: u32) -> Frame<u32> {
+        Frame {
+            header: FrameHeader {
+                sensor_id: 3,
+                epoch: 1_700_000_000,
+                sequence,
+                uptime_ms: 123_456,
+            },
+            payload: 0xdead_beef,
+        }
+    }
+
+    fn encode(frame: &Frame<u32>, buf: &mut [u8; MAX_FRAME_SIZE]) -> usize {
+        encode_frame(frame, &KEY, buf).unwrap().len()
+    }
+
+    fn decode(data: &[u8]) -> Result<Frame<u32>, FrameError> {
+        decode_frame(data, |sensor_id| (sensor_id == 3).then_some(KEY))
+    }
+
+    #[test]
+    fn round_trips_frames() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let len = encode(&frame(42), &mut buf);
+
+        let decoded = decode(&buf[..len]).unwrap();
+
+        assert_eq!(decoded.header, frame(42).header);
+        assert_eq!(decoded.payload, 0xdead_beef);
+        assert_eq!(decoded.header.counter(), 1_700_000_000 << 32 | 42);
+    }
+
+    #[test]
+    fn rejects_frames_without_the_magic() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let len = encode(&frame(1), &mut buf);
+        buf[0] ^= 0xff;
+
+        assert_eq!(decode(&buf[..len]).unwrap_err(), FrameError::InvalidMagic);
+        assert_eq!(decode(&buf[..1]).unwrap_err(), FrameError::TooShort);
+    }
+
+    #[test]
+    fn rejects_other_protocol_versions() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let len = encode(&frame(1), &mut buf);
+        buf[PROTOCOL_MAGIC.len()] = PROTOCOL_VERSION - 1;
+
+        assert_eq!(
+            decode(&buf[..len]).unwrap_err(),
+            FrameError::UnsupportedVersion(PROTOCOL_VERSION - 1)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tags() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let len = encode(&frame(1), &mut buf);
+
+        let mut tampered = buf;
+        tampered[len - 1] ^= 0x01;
+        assert_eq!(
+            decode(&tampered[..len]).unwrap_err(),
+            FrameError::InvalidTag
+        );
+
+        // The header is authenticated too.
+        let mut tampered = buf;
+        tampered[PREAMBLE_SIZE + 2] ^= 0x01;
+        assert_eq!(
+            decode(&tampered[..len]).unwrap_err(),
+            FrameError::InvalidTag
+        );
+
+        let wrong_key = decode_frame::<u32>(&buf[..len], |_| Some([8; KEY_SIZE]));
+        assert_eq!(wrong_key.unwrap_err(), FrameError::InvalidTag);
+    }
+
+    #[test]
+    fn rejects_frames_of_unknown_sensors() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let len = encode(&frame(1), &mut buf);
+
+        let unknown = decode_frame::<u32>(&buf[..len], |_| None);
+
+        assert_eq!(unknown.unwrap_err(), FrameError::UnknownSensor(3));
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        encode(&frame(1), &mut buf);
+
+        assert_eq!(
+            decode(&buf[..PREAMBLE_SIZE + TAG_SIZE - 1]).unwrap_err(),
+            FrameError::TooShort
+        );
+        assert_eq!(
+            decode(&buf[..PREAMBLE_SIZE + TAG_SIZE]).unwrap_err(),
+            FrameError::Malformed
+        );
+    }
+
+    #[test]
+    fn parses_hex_keys() {
+        let key =
+            parse_key("000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F").unwrap();
+
+        for (i, byte) in key.iter().enumerate() {
+            assert_eq!(*byte as usize, i);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert_eq!(parse_key("00ff"), Err(KeyError::InvalidLength(4)));
+        assert_eq!(
+            parse_key(&"0g".repeat(KEY_SIZE)),
+            Err(KeyError::InvalidCharacter('g'))
+        );
+        // Multi-byte characters are not hex digits even if the byte length fits.
+        assert_eq!(
+            parse_key(&"ä".repeat(KEY_SIZE)),
+            Err(KeyError::InvalidLength(KEY_SIZE))
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use clap::Parser;
+use sensor_lib::protocol::{SensorKey, parse_key};
 use sensor_lib::{Environment, SensorConfig};
 use std::error::Error;
 use std::io::Write;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Parser)]
 struct Cli {
@@ -28,6 +30,10 @@ struct Cli {
     #[clap(long)]
     longitude: f64,
 
+    /// Hex encoded key of the sensor, as registered in the backend.
+    #[clap(long, value_parser = parse_key)]
+    key: SensorKey,
+
     #[clap(long)]
     serial_port: String,
 }
@@ -43,6 +49,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         &cli.password.as_str()[..cli.password.as_str().len().min(63)],
     )?;
 
+    // Every upload starts a new epoch of the frame counter, so that frames sent before the node
+    // restarted cannot be replayed.
+    let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+
     let config_data = postcard::to_allocvec(&SensorConfig {
         collector_network_ssid: ssid,
         collector_network_password: password,
@@ -54,6 +64,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         latitude: cli.latitude,
         longitude: cli.longitude,
         environment: Environment::FreeSpace,
+        key: cli.key,
+        epoch,
     })?;
 
     let _ = serial_port.write(&config_data)?;
@@ -17,7 +17,9 @@ use crate::driver::wifi::WifiStaDriver;
 use crate::sniffer::wifi::WifiSniffer;
 use alloc::string::ToString;
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either3, select3};
 use embassy_net::Runner;
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use esp_alloc as _;
 use esp_backtrace as _;
 use esp_hal::clock::CpuClock;
@@ -27,13 +29,33 @@ use esp_hal::timer::timg::TimerGroup;
 use esp_radio::wifi;
 use esp_radio::wifi::WifiDevice;
 use log::{LevelFilter, info, trace};
-use sensor_lib::{SensorConfig, SensorPacket};
+use sensor_lib::{
+    MAX_FIRMWARE_VERSION_LENGTH, MeasurementBatch, SensorConfig, SensorInfo, SensorMessage,
+};
+use sensor_lib::protocol::{Frame, FrameHeader, MAX_FRAME_SIZE, encode_frame};
 use static_cell::make_static;
 
-const SENSOR_PACKET_SIZE: usize = size_of::<SensorPacket>();
-
 esp_bootloader_esp_idf::esp_app_desc!();
 
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Maximum time a measurement is held back before a partially filled batch is sent.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn sensor_info(config: &SensorConfig) -> SensorInfo {
+    let mut firmware_version = heapless::String::<MAX_FIRMWARE_VERSION_LENGTH>::new();
+    let _ = firmware_version.push_str(env!("CARGO_PKG_VERSION"));
+
+    SensorInfo {
+        sensor_id: config.sensor_id,
+        y: config.y,
+        x: config.x,
+        latitude: config.latitude,
+        longitude: config.longitude,
+        environment: config.environment,
+        firmware_version,
+    }
+}
+
 #[esp_rtos::main]
 async fn main(spawner: Spawner) {
     esp_println::logger::init_logger(LevelFilter::Info);
@@ -101,16 +123,61 @@ async fn main(spawner: Spawner) {
     let udp_socket = wifi_driver.udp_socket().unwrap();
 
     let mut wifi_sniffer = WifiSniffer::new(interfaces.sniffer, &config).unwrap();
+    let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+    let mut epoch = config.epoch;
+    let mut sequence: u32 = 0;
+    let mut heartbeat = Ticker::every(HEARTBEAT_INTERVAL);
+    let mut batch = MeasurementBatch::new();
+    let mut batch_deadline = Instant::MAX;
+    let mut message = SensorMessage::Hello(sensor_info(&config));
     loop {
-        let sensor_packet = wifi_sniffer.receive().await;
-        let sensor_packet_data = postcard::to_vec::<_, SENSOR_PACKET_SIZE>(&sensor_packet).unwrap();
+        let frame = Frame {
+            header: FrameHeader {
+                sensor_id: config.sensor_id,
+                epoch,
+                sequence,
+                uptime_ms: embassy_time::Instant::now().as_millis(),
+            },
+            payload: message,
+        };
+        sequence = sequence.wrapping_add(1);
+        if sequence == 0 {
+            epoch = epoch.wrapping_add(1);
+        }
+
+        let frame_data = encode_frame(&frame, &config.key, &mut frame_buf).unwrap();
 
         let _ = udp_socket
             .send_to(
-                &sensor_packet_data,
+                frame_data,
                 (config.collector_service_ip, config.collector_service_port),
             )
             .await;
+
+        message = loop {
+            let flush = Timer::at(batch_deadline);
+
+            match select3(wifi_sniffer.receive(), heartbeat.next(), flush).await {
+                Either3::First(measurement) => {
+                    if batch.is_empty() {
+                        batch_deadline = Instant::now() + BATCH_TIMEOUT;
+                    }
+
+                    // Never fails, full batches are sent right away.
+                    let _ = batch.push(measurement);
+
+                    if batch.is_full() {
+                        batch_deadline = Instant::MAX;
+                        break SensorMessage::MeasurementBatch(batch.take());
+                    }
+                }
+                Either3::Second(()) => break SensorMessage::Heartbeat(sensor_info(&config)),
+                Either3::Third(()) => {
+                    batch_deadline = Instant::MAX;
+                    break SensorMessage::MeasurementBatch(batch.take());
+                }
+            }
+        };
     }
 }
 
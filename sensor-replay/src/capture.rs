@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+pub const LINKTYPE_IEEE802_11_RADIOTAP: u32 = 127;
+
+/// A captured packet with its link type and capture time in microseconds since the unix epoch.
+pub struct CapturedPacket {
+    pub link_type: u32,
+    pub timestamp_us: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(self, data: &[u8]) -> u16 {
+        let bytes = [data[0], data[1]];
+
+        match self {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, data: &[u8]) -> u32 {
+        let bytes = [data[0], data[1], data[2], data[3]];
+
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Reads all packets of a pcap or pcapng file.
+pub fn read_capture(path: &Path) -> anyhow::Result<Vec<CapturedPacket>> {
+    let data = std::fs::read(path)
+        .map_err(|e| anyhow!("Failed to read capture {}: {}", path.display(), e))?;
+
+    if data.len() < 4 {
+        bail!("{} is not a capture file", path.display());
+    }
+
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+
+    let packets = if magic == PCAPNG_SECTION_HEADER {
+        read_pcapng(&data)
+    } else {
+        read_pcap(&data)
+    };
+
+    packets.map_err(|e| anyhow!("Failed to parse capture {}: {}", path.display(), e))
+}
+
+fn read_pcap(data: &[u8]) -> anyhow::Result<Vec<CapturedPacket>> {
+    if data.len() < PCAP_HEADER_SIZE {
+        bail!("truncated pcap header");
+    }
+
+    let (endian, nanos) = match (
+        u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+    ) {
+        (PCAP_MAGIC_MICROS, _) => (Endian::Little, false),
+        (PCAP_MAGIC_NANOS, _) => (Endian::Little, true),
+        (_, PCAP_MAGIC_MICROS) => (Endian::Big, false),
+        (_, PCAP_MAGIC_NANOS) => (Endian::Big, true),
+        _ => bail!("unknown file format"),
+    };
+
+    let link_type = endian.u32(&data[20..]) & 0x0fff_ffff;
+
+    let mut packets = Vec::new();
+    let mut offset = PCAP_HEADER_SIZE;
+
+    while offset + PCAP_RECORD_HEADER_SIZE <= data.len() {
+        let record = &data[offset..];
+        let seconds = endian.u32(record) as u64;
+        let fraction = endian.u32(&record[4..]) as u64;
+        let len = endian.u32(&record[8..]) as usize;
+
+        let start = offset + PCAP_RECORD_HEADER_SIZE;
+        let Some(packet) = data.get(start..start + len) else {
+            bail!("truncated packet record");
+        };
+
+        packets.push(CapturedPacket {
+            link_type,
+            timestamp_us: seconds * 1_000_000 + if nanos { fraction / 1000 } else { fraction },
+            data: packet.to_vec(),
+        });
+
+        offset = start + len;
+    }
+
+    Ok(packets)
+}
+
+struct Interface {
+    link_type: u32,
+    /// Timestamp units per second.
+    resolution: u64,
+}
+
+fn read_pcapng(data: &[u8]) -> anyhow::Result<Vec<CapturedPacket>> {
+    let mut packets = Vec::new();
+    let mut interfaces = Vec::new();
+    let mut endian = Endian::Little;
+    let mut offset = 0;
+
+    while offset + 12 <= data.len() {
+        let block = &data[offset..];
+
+        if u32::from_le_bytes([block[0], block[1], block[2], block[3]]) == PCAPNG_SECTION_HEADER {
+            endian = match u32::from_le_bytes([block[8], block[9], block[10], block[11]]) {
+                PCAPNG_BYTE_ORDER_MAGIC => Endian::Little,
+                _ => Endian::Big,
+            };
+            // Interface IDs are local to their section.
+            interfaces.clear();
+        }
+
+        let block_type = endian.u32(block);
+        let len = endian.u32(&block[4..]) as usize;
+
+        if len < 12 || offset + len > data.len() {
+            bail!("truncated block");
+        }
+
+        let body = &block[8..len - 4];
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                interfaces.push(Interface {
+                    link_type: endian.u16(body) as u32,
+                    resolution: interface_resolution(endian, &body[8..]),
+                });
+            }
+            PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                let interface = interfaces
+                    .get(endian.u32(body) as usize)
+                    .ok_or_else(|| anyhow!("packet of unknown interface"))?;
+                let timestamp =
+                    (endian.u32(&body[4..]) as u64) << 32 | endian.u32(&body[8..]) as u64;
+                let captured = endian.u32(&body[12..]) as usize;
+
+                let Some(packet) = body.get(20..20 + captured) else {
+                    bail!("truncated packet block");
+                };
+
+                packets.push(CapturedPacket {
+                    link_type: interface.link_type,
+                    timestamp_us: (timestamp as u128 * 1_000_000 / interface.resolution as u128)
+                        as u64,
+                    data: packet.to_vec(),
+                });
+            }
+            _ => {}
+        }
+
+        offset += len;
+    }
+
+    Ok(packets)
+}
+
+/// Reads the `if_tsresol` option, microseconds if it is missing.
+fn interface_resolution(endian: Endian, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = endian.u16(options);
+        let len = endian.u16(&options[2..]) as usize;
+
+        if code == PCAPNG_OPTION_END {
+            break;
+        }
+
+        if code == PCAPNG_OPTION_TSRESOL && len >= 1 && options.len() > 4 {
+            let value = options[4];
+            let exponent = (value & 0x7f) as u32;
+
+            return match value & 0x80 {
+                0 => 10u64.saturating_pow(exponent),
+                _ => 2u64.saturating_pow(exponent),
+            };
+        }
+
+        // Options are padded to 32 bits.
+        let padded = 4 + len.div_ceil(4) * 4;
+        options = options.get(padded..).unwrap_or_default();
+    }
+
+    1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PCAP: &[u8] = include_bytes!("../fixtures/capture/probe_requests.pcap");
+    const PCAP_NANOS_BE: &[u8] = include_bytes!("../fixtures/capture/probe_requests_nanos_be.pcap");
+    const PCAPNG: &[u8] = include_bytes!("../fixtures/capture/probe_requests.pcapng");
+
+    const FCS: &[u8] = include_bytes!("../fixtures/radiotap/fcs.bin");
+    const EXTENDED_PRESENT: &[u8] = include_bytes!("../fixtures/radiotap/extended_present.bin");
+
+    fn assert_packets(packets: &[CapturedPacket]) {
+        assert_eq!(packets.len(), 2);
+
+        assert_eq!(packets[0].link_type, LINKTYPE_IEEE802_11_RADIOTAP);
+        assert_eq!(packets[0].timestamp_us, 1_700_000_000_250_000);
+        assert_eq!(packets[0].data, FCS);
+
+        assert_eq!(packets[1].link_type, LINKTYPE_IEEE802_11_RADIOTAP);
+        assert_eq!(packets[1].timestamp_us, 1_700_000_001_000_500);
+        assert_eq!(packets[1].data, EXTENDED_PRESENT);
+    }
+
+    #[test]
+    fn reads_pcap() {
+        assert_packets(&read_pcap(PCAP).unwrap());
+    }
+
+    #[test]
+    fn reads_big_endian_pcap_with_nanoseconds() {
+        assert_packets(&read_pcap(PCAP_NANOS_BE).unwrap());
+    }
+
+    #[test]
+    fn reads_pcapng_with_timestamp_resolution() {
+        assert_packets(&read_pcapng(PCAPNG).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_captures() {
+        assert!(read_pcap(&PCAP[..PCAP.len() - 1]).is_err());
+        assert!(read_pcapng(&PCAPNG[..PCAPNG.len() - 1]).is_err());
+        assert!(read_pcap(&PCAP[..PCAP_HEADER_SIZE - 1]).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use clap::Parser;
+use sensor_lib::probe::ProbeRequest;
+use sensor_lib::protocol::{Frame, FrameHeader, MAX_FRAME_SIZE, encode_frame};
+use sensor_lib::{Measurement, SensorInfo, SensorMessage};
+
+use crate::capture::{LINKTYPE_IEEE802_11_RADIOTAP, read_capture};
+use crate::mapping::{Mapping, VirtualSensor};
+
+mod capture;
+mod mapping;
+mod radiotap;
+
+/// Slowest playback speed, slower speeds would stretch the offsets beyond what a `Duration` holds.
+const MIN_SPEED: f64 = 0.001;
+
+/// Replays the probe requests of pcap/pcapng captures with radiotap headers as virtual sensors.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// TOML file assigning the captures to virtual sensors.
+    mapping: PathBuf,
+
+    /// UDP address of the backend.
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    backend: SocketAddr,
+
+    /// Playback speed relative to the capture, at least 0.001, 0 sends everything as fast as
+    /// possible.
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+}
+
+/// A probe request received by a virtual sensor.
+struct Event {
+    timestamp_us: u64,
+    sensor: usize,
+    measurement: Measurement,
+}
+
+struct Sender<'a> {
+    socket: UdpSocket,
+    sensor: &'a VirtualSensor,
+    epoch: u32,
+    sequence: u32,
+}
+
+impl Sender<'_> {
+    fn send(&mut self, uptime_ms: u64, message: SensorMessage) -> anyhow::Result<()> {
+        let frame = Frame {
+            header: FrameHeader {
+                sensor_id: self.sensor.id,
+                epoch: self.epoch,
+                sequence: self.sequence,
+                uptime_ms,
+            },
+            payload: message,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let bytes = encode_frame(&frame, &self.sensor.key, &mut buf)?;
+
+        self.socket.send(bytes)?;
+
+        Ok(())
+    }
+}
+
+fn read_events(sensor_index: usize, sensor: &VirtualSensor) -> anyhow::Result<Vec<Event>> {
+    let packets = read_capture(&sensor.capture)?;
+    let start = packets.first().map(|p| p.timestamp_us).unwrap_or_default();
+
+    let mut events = Vec::new();
+    let mut skipped = 0;
+
+    for packet in &packets {
+        if packet.link_type != LINKTYPE_IEEE802_11_RADIOTAP {
+            bail!(
+                "{} has link type {}, only radiotap captures are supported",
+                sensor.capture.display(),
+                packet.link_type
+            );
+        }
+
+        let Some(radiotap) = radiotap::parse(&packet.data) else {
+            skipped += 1;
+            continue;
+        };
+        let Some(rssi) = radiotap.rssi else {
+            skipped += 1;
+            continue;
+        };
+        let Ok(probe_request) = ProbeRequest::parse(radiotap.frame, radiotap.with_fcs) else {
+            skipped += 1;
+            continue;
+        };
+
+        let mut fingerprint = [0; 8];
+        fingerprint[0..6].copy_from_slice(&probe_request.transmitter);
+
+        events.push(Event {
+            timestamp_us: packet.timestamp_us,
+            sensor: sensor_index,
+            measurement: Measurement {
+                sensor_id: sensor.id,
+                fingerprint: u64::from_be_bytes(fingerprint),
+                signature: Some(probe_request.signature()),
+                sequence_number: Some(probe_request.sequence_number),
+                rssi,
+                // Records of a capture are not necessarily in order.
+                timestamp_ms: packet.timestamp_us.saturating_sub(start) / 1000,
+            },
+        });
+    }
+
+    println!(
+        "Sensor {}: {} probe requests, {} other packets in {}",
+        sensor.id,
+        events.len(),
+        skipped,
+        sensor.capture.display()
+    );
+
+    Ok(events)
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    if !(cli.speed == 0.0 || (cli.speed.is_finite() && cli.speed >= MIN_SPEED)) {
+        bail!(
+            "Speed must be 0 or at least {}, got {}",
+            MIN_SPEED,
+            cli.speed
+        );
+    }
+
+    let mapping = Mapping::load(&cli.mapping)?;
+
+    let mut events = Vec::new();
+    for (i, sensor) in mapping.sensors.iter().enumerate() {
+        events.extend(read_events(i, sensor)?);
+    }
+    events.sort_by_key(|e| e.timestamp_us);
+
+    let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+
+    let mut senders = Vec::new();
+    for sensor in &mapping.sensors {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(cli.backend)?;
+
+        let mut sender = Sender {
+            socket,
+            sensor,
+            epoch,
+            sequence: 0,
+        };
+
+        let info = SensorInfo {
+            sensor_id: sensor.id,
+            y: sensor.y,
+            x: sensor.x,
+            latitude: sensor.latitude,
+            longitude: sensor.longitude,
+            environment: sensor.environment,
+            firmware_version: "sensor-replay".try_into().unwrap(),
+        };
+        sender.send(0, SensorMessage::Hello(info))?;
+
+        senders.push(sender);
+    }
+
+    let Some(first) = events.first().map(|e| e.timestamp_us) else {
+        println!("No probe requests to replay");
+        return Ok(());
+    };
+
+    let start = Instant::now();
+
+    for event in &events {
+        if cli.speed > 0.0 {
+            let offset = Duration::from_micros(event.timestamp_us - first).div_f64(cli.speed);
+            std::thread::sleep((start + offset).saturating_duration_since(Instant::now()));
+        }
+
+        senders[event.sensor].send(
+            event.measurement.timestamp_ms,
+            SensorMessage::Measurement(event.measurement),
+        )?;
+    }
+
+    println!(
+        "Replayed {} probe requests in {:.1}s",
+        events.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
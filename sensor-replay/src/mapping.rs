@@ -0,0 +1,57 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use sensor_lib::Environment;
+use sensor_lib::protocol::{SensorKey, parse_key};
+use serde::{Deserialize, Deserializer};
+
+/// Assigns every capture file to a virtual sensor.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    pub sensors: Vec<VirtualSensor>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualSensor {
+    pub id: u8,
+    /// Relative to the mapping file.
+    pub capture: PathBuf,
+    pub y: f64,
+    pub x: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default = "default_environment")]
+    pub environment: Environment,
+    /// Hex encoded, the zero key if it is missing.
+    #[serde(default, deserialize_with = "deserialize_key")]
+    pub key: SensorKey,
+}
+
+fn default_environment() -> Environment {
+    Environment::FreeSpace
+}
+
+fn deserialize_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SensorKey, D::Error> {
+    let key = String::deserialize(deserializer)?;
+
+    parse_key(&key).map_err(serde::de::Error::custom)
+}
+
+impl Mapping {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read mapping {}: {}", path.display(), e))?;
+
+        let mut mapping: Mapping = toml::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse mapping {}: {}", path.display(), e))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for sensor in &mut mapping.sensors {
+            sensor.capture = base.join(&sensor.capture);
+        }
+
+        Ok(mapping)
+    }
+}
@@ -0,0 +1,126 @@
+/// Bit of the last present bitmap word that announces another word.
+const PRESENT_EXTENDED: u32 = 1 << 31;
+
+const TSFT: u32 = 0;
+const FLAGS: u32 = 1;
+const RATE: u32 = 2;
+const CHANNEL: u32 = 3;
+const FHSS: u32 = 4;
+const ANTENNA_SIGNAL: u32 = 5;
+
+/// The frame check sequence is appended to the 802.11 frame.
+const FLAG_FCS: u8 = 0x10;
+
+/// Alignment and size of the fields in front of the antenna signal.
+const FIELDS: [(u32, usize, usize); 6] = [
+    (TSFT, 8, 8),
+    (FLAGS, 1, 1),
+    (RATE, 1, 1),
+    (CHANNEL, 2, 4),
+    (FHSS, 1, 2),
+    (ANTENNA_SIGNAL, 1, 1),
+];
+
+pub struct RadiotapFrame<'a> {
+    pub rssi: Option<i8>,
+    pub with_fcs: bool,
+    pub frame: &'a [u8],
+}
+
+/// Strips the radiotap header of a captured packet and reads the signal strength and flags.
+pub fn parse(packet: &[u8]) -> Option<RadiotapFrame<'_>> {
+    if packet.len() < 8 || packet[0] != 0 {
+        return None;
+    }
+
+    // The length covers at least the version, length and first present word read below.
+    let len = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+    if len < 8 {
+        return None;
+    }
+    let header = packet.get(..len)?;
+
+    let present = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    // The fields start after the last present bitmap word.
+    let mut offset = 8;
+    let mut word = present;
+    while word & PRESENT_EXTENDED != 0 {
+        let next = header.get(offset..offset + 4)?;
+        word = u32::from_le_bytes([next[0], next[1], next[2], next[3]]);
+        offset += 4;
+    }
+
+    let mut rssi = None;
+    let mut flags = 0;
+
+    for (bit, align, size) in FIELDS {
+        if present & 1 << bit == 0 {
+            continue;
+        }
+
+        offset = offset.next_multiple_of(align);
+        let field = header.get(offset..offset + size)?;
+
+        match bit {
+            FLAGS => flags = field[0],
+            ANTENNA_SIGNAL => rssi = Some(field[0] as i8),
+            _ => {}
+        }
+
+        offset += size;
+    }
+
+    Some(RadiotapFrame {
+        rssi,
+        with_fcs: flags & FLAG_FCS != 0,
+        frame: &packet[len..],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sensor_lib::probe::ProbeRequest;
+
+    const FCS: &[u8] = include_bytes!("../fixtures/radiotap/fcs.bin");
+    const EXTENDED_PRESENT: &[u8] = include_bytes!("../fixtures/radiotap/extended_present.bin");
+    const TRUNCATED: &[u8] = include_bytes!("../fixtures/radiotap/truncated.bin");
+
+    #[test]
+    fn reads_signal_and_fcs_flag() {
+        let radiotap = parse(FCS).unwrap();
+
+        assert_eq!(radiotap.rssi, Some(-52));
+        assert!(radiotap.with_fcs);
+        assert_eq!(radiotap.frame.len(), FCS.len() - 15);
+        assert!(ProbeRequest::parse(radiotap.frame, radiotap.with_fcs).is_ok());
+    }
+
+    #[test]
+    fn skips_extended_present_words_and_aligns_fields() {
+        let radiotap = parse(EXTENDED_PRESENT).unwrap();
+
+        assert_eq!(radiotap.rssi, Some(-71));
+        assert!(!radiotap.with_fcs);
+
+        let request = ProbeRequest::parse(radiotap.frame, radiotap.with_fcs).unwrap();
+        assert_eq!(request.transmitter, [0x7a, 0x42, 0x9b, 0x10, 0xc3, 0x77]);
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        assert!(parse(TRUNCATED).is_none());
+        assert!(parse(&FCS[..4]).is_none());
+    }
+
+    #[test]
+    fn rejects_lengths_shorter_than_the_header() {
+        for len in [0u16, 4, 7] {
+            let mut packet = FCS.to_vec();
+            packet[2..4].copy_from_slice(&len.to_le_bytes());
+
+            assert!(parse(&packet).is_none(), "length {len}");
+        }
+    }
+}
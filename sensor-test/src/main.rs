@@ -1,53 +1,105 @@
 use std::net::UdpSocket;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use sensor_lib::Environment::FreeSpace;
-use sensor_lib::SensorPacket;
+use sensor_lib::protocol::{
+    Frame, FrameHeader, KEY_SIZE, MAX_FRAME_SIZE, SensorKey, encode_frame, parse_key,
+};
+use sensor_lib::{Measurement, MeasurementBatch, SensorInfo, SensorMessage};
+
+struct Sender {
+    socket: UdpSocket,
+    key: SensorKey,
+    epoch: u32,
+}
+
+fn send(
+    sender: &Sender,
+    sensor_id: u8,
+    sequence: u32,
+    message: SensorMessage,
+) -> anyhow::Result<()> {
+    let frame = Frame {
+        header: FrameHeader {
+            sensor_id,
+            epoch: sender.epoch,
+            sequence,
+            uptime_ms: 0,
+        },
+        payload: message,
+    };
+
+    let mut buf = [0u8; MAX_FRAME_SIZE];
+    let bytes = encode_frame(&frame, &sender.key, &mut buf)?;
+
+    sender.socket.send(bytes)?;
+
+    Ok(())
+}
 
 fn main() -> anyhow::Result<()> {
     let socket = UdpSocket::bind("0.0.0.0:0")?;
     socket.connect("0.0.0.0:3000")?;
 
-    let packet = SensorPacket {
-        sensor_id: 1,
-        y: 0.0,
-        x: 0.0,
-        latitude: 50.56484445024739,
-        longitude: 9.684520461933687,
-        rssi: -50,
-        fingerprint: 0xABC123,
-        environment: FreeSpace,
+    // All sensors share the key given as hex in `SENSOR_KEY`, the zero key by default.
+    let key = match std::env::var("SENSOR_KEY") {
+        Ok(hex_key) => parse_key(&hex_key)?,
+        Err(_) => [0u8; KEY_SIZE],
     };
-    let bytes = postcard::to_allocvec(&packet)?;
-
-    socket.send(&bytes)?;
-
-    let packet = SensorPacket {
-        sensor_id: 2,
-        y: 0.0,
-        x: 4.0,
-        latitude: 50.56494466501721,
-        longitude: 9.684520461933687,
-        rssi: -50,
-        fingerprint: 0xABC123,
-        environment: FreeSpace,
-    };
-    let bytes = postcard::to_allocvec(&packet)?;
-
-    socket.send(&bytes)?;
-
-    let packet = SensorPacket {
-        sensor_id: 3,
-        y: 3.0,
-        x: 2.0,
-        latitude: 50.5648945576323,
-        longitude: 9.684697512562593,
-        rssi: -50,
-        fingerprint: 0xABC123,
-        environment: FreeSpace,
+
+    let sender = Sender {
+        socket,
+        key,
+        epoch: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32,
     };
-    let bytes = postcard::to_allocvec(&packet)?;
 
-    socket.send(&bytes)?;
+    let sequence = std::env::args()
+        .nth(1)
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(0);
+
+    let sensors = [
+        (1, 0.0, 0.0, 50.56484445024739, 9.684520461933687),
+        (2, 0.0, 4.0, 50.56494466501721, 9.684520461933687),
+        (3, 3.0, 2.0, 50.5648945576323, 9.684697512562593),
+    ];
+
+    for (sensor_id, y, x, latitude, longitude) in sensors {
+        let info = SensorInfo {
+            sensor_id,
+            y,
+            x,
+            latitude,
+            longitude,
+            environment: FreeSpace,
+            firmware_version: "sensor-test".try_into().unwrap(),
+        };
+
+        send(&sender, sensor_id, sequence, SensorMessage::Hello(info))?;
+
+        let mut batch = MeasurementBatch::new();
+
+        for timestamp_ms in 0..10 {
+            let measurement = Measurement {
+                sensor_id,
+                fingerprint: 0xABC123,
+                signature: None,
+                sequence_number: None,
+                rssi: -50,
+                timestamp_ms,
+            };
+
+            batch.push(measurement).unwrap();
+        }
+
+        send(
+            &sender,
+            sensor_id,
+            sequence.wrapping_add(1),
+            SensorMessage::MeasurementBatch(batch),
+        )?;
+    }
 
     Ok(())
 }